@@ -0,0 +1,88 @@
+use std::sync::{Mutex, PoisonError};
+
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use syslog::{Facility, Formatter3164, Logger, LoggerBackend};
+
+const DEFAULT_SYSLOG_PORT: u16 = 514;
+
+/// Sends an access log line for every redirect to a syslog server, in ArcSight
+/// CEF, for security teams that collect these in a SIEM.
+pub struct SyslogSink {
+    logger: Mutex<Logger<LoggerBackend, Formatter3164>>,
+}
+
+/// What a single redirect exposes to the access log.
+pub struct RedirectEvent<'a> {
+    pub alias: &'a str,
+    pub target_url: &'a str,
+    pub ip_hash: Option<String>,
+    pub user_agent: Option<&'a str>,
+}
+
+impl SyslogSink {
+    /// Sends to `host` over UDP, on port 514 unless `host` names one.
+    pub fn connect(host: &str) -> Result<Self> {
+        let has_port = host
+            .rsplit_once(':')
+            .is_some_and(|(_, port)| port.parse::<u16>().is_ok());
+        let server = if has_port {
+            host.to_string()
+        } else {
+            format!("{host}:{DEFAULT_SYSLOG_PORT}")
+        };
+        let formatter = Formatter3164 {
+            facility: Facility::LOG_USER,
+            hostname: None,
+            process: env!("CARGO_PKG_NAME").to_string(),
+            pid: std::process::id(),
+        };
+        let logger = syslog::udp(formatter, "0.0.0.0:0", &server)
+            .map_err(|err| anyhow!("failed to open syslog socket to {server}: {err}"))?;
+        Ok(Self {
+            logger: Mutex::new(logger),
+        })
+    }
+
+    /// Logs `event`. Syslog is best effort, so failures are only reported locally.
+    pub fn log_redirect(&self, event: &RedirectEvent) {
+        let result = self
+            .logger
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .info(cef(event));
+        if let Err(err) = result {
+            tracing::warn!("failed to send access log to syslog: {err}");
+        }
+    }
+}
+
+fn cef(event: &RedirectEvent) -> String {
+    let mut extension = vec![
+        format!("rt={}", Utc::now().timestamp_millis()),
+        "cs1Label=alias".to_string(),
+        format!("cs1={}", escape(event.alias)),
+        format!("request={}", escape(event.target_url)),
+    ];
+    if let Some(user_agent) = event.user_agent {
+        extension.push(format!("requestClientApplication={}", escape(user_agent)));
+    }
+    if let Some(ip_hash) = &event.ip_hash {
+        extension.push("cs2Label=ipHash".to_string());
+        extension.push(format!("cs2={}", escape(ip_hash)));
+    }
+    format!(
+        "CEF:0|ito|ito|{}|redirect|Link redirect|3|{}",
+        env!("CARGO_PKG_VERSION"),
+        extension.join(" ")
+    )
+}
+
+/// Escapes a CEF extension value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('=', "\\=")
+        .replace('\r', "\\r")
+        .replace('\n', "\\n")
+}
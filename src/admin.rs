@@ -0,0 +1,441 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::ErrorKind,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use askama::Template;
+use axum::{
+    extract::{Form, Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+    Json,
+};
+use rusqlite::{
+    backup::Backup, params, params_from_iter, types::Null, Connection, OptionalExtension,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tower_sessions::Session;
+
+use crate::{
+    api, audit,
+    auth::{self, Scope},
+    clicks,
+    config::Config,
+    db, handle_sqlite_err, totp,
+    users::{self, User},
+    HtmlTemplate, ItoError, ItoJsonError, ItoPool, ReadPool, LIST_LINKS_SQL, REDIRECT_LOOKUP_SQL,
+};
+
+/// Keeps a server-side failure to follow `alias` for the dashboard. This is
+/// best effort, so failing to record it is only logged.
+pub async fn record_link_error(pool: &ItoPool, alias: String, err: &ItoError) {
+    let message = format!("{:#}", err.err);
+    let result = db::interact(pool, move |conn| {
+        conn.execute(
+            "INSERT INTO link_errors (alias, message, created_at)
+            VALUES (?1, ?2, strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))",
+            params![alias, message],
+        )
+        .map_err(ItoError::from)
+    })
+    .await;
+    if let Err(err) = result {
+        tracing::warn!("failed to record link error: {:#}", err.err);
+    }
+}
+
+pub struct LinkClicks {
+    alias: String,
+    click_count: i64,
+}
+
+pub struct RecentLink {
+    alias: String,
+    created_at: Option<String>,
+}
+
+pub struct LinkError {
+    alias: String,
+    message: String,
+    created_at: String,
+}
+
+/// What the admin dashboard shows about the whole service.
+pub struct AdminStats {
+    total_links: i64,
+    active_links: i64,
+    expired_links: i64,
+    clicks_today: i64,
+    clicks_this_week: i64,
+    clicks_this_month: i64,
+    top_links: Vec<LinkClicks>,
+    recent_links: Vec<RecentLink>,
+    recent_errors: Vec<LinkError>,
+}
+
+/// Gathers `AdminStats` in one read transaction, so every number describes
+/// the same moment.
+pub fn fetch_admin_stats(conn: &mut Connection) -> rusqlite::Result<AdminStats> {
+    let tx = conn.transaction()?;
+    let (total_links, expired_links) = tx.query_row(
+        "SELECT COUNT(*),
+            COUNT(*) FILTER (WHERE expires_at <= strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))
+        FROM links",
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    // The week and month are the last 7 and 30 days, today included.
+    let (clicks_today, clicks_this_week, clicks_this_month) = tx.query_row(
+        "SELECT COUNT(*) FILTER (WHERE clicked_at >= date('now')),
+            COUNT(*) FILTER (WHERE clicked_at >= date('now', '-6 days')),
+            COUNT(*)
+        FROM link_clicks WHERE clicked_at >= date('now', '-29 days')",
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    )?;
+    let top_links = tx
+        .prepare("SELECT alias, click_count FROM links ORDER BY click_count DESC, id LIMIT 5")?
+        .query_map([], |row| {
+            Ok(LinkClicks {
+                alias: row.get(0)?,
+                click_count: row.get(1)?,
+            })
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    let recent_links = tx
+        .prepare("SELECT alias, created_at FROM links ORDER BY id DESC LIMIT 5")?
+        .query_map([], |row| {
+            Ok(RecentLink {
+                alias: row.get(0)?,
+                created_at: row.get(1)?,
+            })
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    let recent_errors = tx
+        .prepare("SELECT alias, message, created_at FROM link_errors ORDER BY id DESC LIMIT 5")?
+        .query_map([], |row| {
+            Ok(LinkError {
+                alias: row.get(0)?,
+                message: row.get(1)?,
+                created_at: row.get(2)?,
+            })
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(AdminStats {
+        total_links,
+        active_links: total_links - expired_links,
+        expired_links,
+        clicks_today,
+        clicks_this_week,
+        clicks_this_month,
+        top_links,
+        recent_links,
+        recent_errors,
+    })
+}
+
+#[derive(Template)]
+#[template(path = "admin.html")]
+struct AdminTemplate {
+    stats: AdminStats,
+}
+
+/// Shows an overview of the service to admins, signed in or holding a token
+/// with the `admin` scope.
+pub async fn dashboard(
+    State(ReadPool(pool)): State<ReadPool>,
+    State(config): State<Arc<Config>>,
+    user: Option<User>,
+    headers: HeaderMap,
+) -> Result<Response, ItoError> {
+    let admin_token = auth::token_claims(&config, &headers)
+        .is_some_and(|claims| claims.scopes.contains(&Scope::Admin));
+    if !admin_token {
+        match user {
+            Some(user) => user.require_admin()?,
+            None => return Ok(Redirect::to("/login").into_response()),
+        }
+    }
+    let stats = db::interact(&pool, |conn| {
+        fetch_admin_stats(conn).map_err(ItoError::from)
+    })
+    .await?;
+    Ok(HtmlTemplate(AdminTemplate { stats }).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct TransferLinkInput {
+    new_owner_id: i64,
+}
+
+#[derive(Serialize)]
+pub struct TransferLinkOutput {
+    link_id: i64,
+    old_owner_id: Option<i64>,
+    new_owner_id: i64,
+}
+
+pub async fn transfer_link(
+    State(pool): State<ItoPool>,
+    user: User,
+    Path(link_id): Path<i64>,
+    Json(input): Json<TransferLinkInput>,
+) -> Result<Json<TransferLinkOutput>, ItoJsonError> {
+    user.require_admin()?;
+    let output = db::interact(&pool, move |conn| {
+        let tx = conn.transaction()?;
+        let old_owner_id: Option<i64> = tx
+            .query_row("SELECT user_id FROM links WHERE id = ?", [link_id], |row| {
+                row.get(0)
+            })
+            .map_err(handle_sqlite_err)?;
+        let new_owner_exists: bool = tx.query_row(
+            "SELECT EXISTS (SELECT 1 FROM users WHERE id = ?)",
+            [input.new_owner_id],
+            |row| row.get(0),
+        )?;
+        if !new_owner_exists {
+            return Err(ItoError {
+                err: anyhow!("user {} does not exist", input.new_owner_id),
+                sc: StatusCode::BAD_REQUEST,
+            });
+        }
+        tx.execute(
+            "UPDATE links SET user_id = ?1 WHERE id = ?2",
+            params![input.new_owner_id, link_id],
+        )?;
+        audit::record(
+            &tx,
+            "transfer_link",
+            Some(user.id),
+            Some(link_id),
+            json!({ "old_user_id": old_owner_id, "new_user_id": input.new_owner_id }),
+        )?;
+        tx.commit()?;
+        Ok(TransferLinkOutput {
+            link_id,
+            old_owner_id,
+            new_owner_id: input.new_owner_id,
+        })
+    })
+    .await?;
+    Ok(Json(output))
+}
+
+#[derive(Deserialize)]
+pub struct ImpersonateInput {
+    /// A current TOTP code when the admin uses two-factor sign in, and their
+    /// password otherwise.
+    confirmation: String,
+}
+
+/// Shows the admin ito as `user_id` sees it, once they have confirmed who
+/// they are again, until `POST /admin/impersonate/stop`.
+pub async fn impersonate(
+    State(pool): State<ItoPool>,
+    user: User,
+    session: Session,
+    Path(user_id): Path<i64>,
+    Form(input): Form<ImpersonateInput>,
+) -> Result<Redirect, ItoError> {
+    user.require_admin()?;
+    if user.impersonated_by.is_some() {
+        return Err(ItoError {
+            err: anyhow!("stop impersonating {} first", user.username),
+            sc: StatusCode::BAD_REQUEST,
+        });
+    }
+    if user_id == user.id {
+        return Err(ItoError {
+            err: anyhow!("admins can't impersonate themselves"),
+            sc: StatusCode::BAD_REQUEST,
+        });
+    }
+    let admin_id = user.id;
+    let (password_hash, totp_secret): (String, Option<String>) = db::interact(&pool, move |conn| {
+        conn.query_row(
+            "SELECT password_hash, totp_secret FROM users WHERE id = ?",
+            [admin_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .map_err(ItoError::from)
+    })
+    .await?;
+    let confirmed = match totp_secret {
+        Some(secret) => {
+            let username = user.username.clone();
+            db::interact(&pool, move |conn| {
+                totp::accept_code(conn, admin_id, &username, &secret, &input.confirmation)
+            })
+            .await?
+        }
+        None => users::verify_password(input.confirmation, password_hash).await?,
+    };
+    if !confirmed {
+        return Err(ItoError {
+            err: anyhow!("invalid confirmation"),
+            sc: StatusCode::FORBIDDEN,
+        });
+    }
+    db::interact(&pool, move |conn| -> Result<_, ItoError> {
+        let username: String = conn
+            .query_row(
+                "SELECT username FROM users WHERE id = ?",
+                [user_id],
+                |row| row.get(0),
+            )
+            .optional()?
+            .ok_or_else(|| ItoError {
+                err: anyhow!("user {user_id} does not exist"),
+                sc: StatusCode::NOT_FOUND,
+            })?;
+        audit::record(
+            conn,
+            "impersonate_user",
+            Some(user.id),
+            None,
+            json!({ "user_id": user_id, "username": username }),
+        )?;
+        Ok(())
+    })
+    .await?;
+    users::start_impersonating(&session, user_id)?;
+    Ok(Redirect::to("/"))
+}
+
+/// The queries whose plans `GET /admin/explain` will show. Only these can be
+/// explained, so the endpoint can't be used to probe arbitrary SQL.
+const EXPLAINABLE_QUERIES: &[(&str, &str)] = &[
+    ("list_links", LIST_LINKS_SQL),
+    ("redirect_lookup", REDIRECT_LOOKUP_SQL),
+    ("links_by_url", api::LINKS_BY_URL_SQL),
+    ("analytics_by_day", clicks::ANALYTICS_BY_DAY_SQL),
+    ("export_clicks", clicks::EXPORT_CLICKS_SQL),
+    ("click_heatmap", clicks::HEATMAP_UTC_SQL),
+];
+
+#[derive(Deserialize)]
+pub struct ExplainParams {
+    q: String,
+}
+
+#[derive(Serialize)]
+pub struct QueryPlanRow {
+    id: i64,
+    parent: i64,
+    detail: String,
+}
+
+/// Returns the `EXPLAIN QUERY PLAN` output for one of `EXPLAINABLE_QUERIES`.
+pub async fn explain_query(
+    State(ReadPool(pool)): State<ReadPool>,
+    Query(params): Query<ExplainParams>,
+) -> Result<Json<Vec<QueryPlanRow>>, ItoJsonError> {
+    let Some((_, sql)) = EXPLAINABLE_QUERIES
+        .iter()
+        .find(|(name, _)| *name == params.q)
+    else {
+        let names: Vec<_> = EXPLAINABLE_QUERIES.iter().map(|(name, _)| *name).collect();
+        return Err(ItoError {
+            err: anyhow!(
+                "unknown query {:?}, expected one of {}",
+                params.q,
+                names.join(", ")
+            ),
+            sc: StatusCode::BAD_REQUEST,
+        }
+        .into());
+    };
+    let plan = db::interact(&pool, move |conn| -> Result<_, ItoError> {
+        let mut statement = conn.prepare(&format!("EXPLAIN QUERY PLAN {sql}"))?;
+        // The plan doesn't depend on the parameter values, but they must all be bound.
+        let nulls = vec![Null; statement.parameter_count()];
+        let rows = statement.query_map(params_from_iter(nulls), |row| {
+            Ok(QueryPlanRow {
+                id: row.get(0)?,
+                parent: row.get(1)?,
+                detail: row.get(3)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    })
+    .await?;
+    Ok(Json(plan))
+}
+
+/// How long a backup pauses between steps, letting other connections in.
+const BACKUP_STEP_PAUSE: Duration = Duration::from_millis(10);
+
+#[derive(Deserialize)]
+pub struct BackupParams {
+    /// A file name in `Config::backup_dir`.
+    dest: String,
+}
+
+#[derive(Serialize)]
+pub struct BackupReport {
+    pages_backed_up: i32,
+    elapsed_ms: u128,
+}
+
+/// Copies the database to `dest` in the backup directory with SQLite's
+/// online backup API, a few pages at a time, so the copy is consistent
+/// without blocking writers for long. Existing files are never overwritten.
+pub async fn backup(
+    State(ReadPool(pool)): State<ReadPool>,
+    State(config): State<Arc<Config>>,
+    Query(params): Query<BackupParams>,
+) -> Result<Json<BackupReport>, ItoJsonError> {
+    let Some(backup_dir) = &config.backup_dir else {
+        return Err(ItoError {
+            err: anyhow!("backups are disabled until ITO_BACKUP_DIR is set"),
+            sc: StatusCode::SERVICE_UNAVAILABLE,
+        }
+        .into());
+    };
+    let name = params.dest;
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\', '\0']) {
+        return Err(ItoError {
+            err: anyhow!("dest must be a file name, not {name:?}"),
+            sc: StatusCode::BAD_REQUEST,
+        }
+        .into());
+    }
+    let path = backup_dir.join(&name);
+    // Creating the file here, rather than checking for it first, means a
+    // file or symlink that appears in the meantime is never written through.
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .map_err(|err| ItoError {
+            sc: match err.kind() {
+                ErrorKind::AlreadyExists => StatusCode::CONFLICT,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            },
+            err: anyhow!("failed to create backup {name}: {err}"),
+        })?;
+    let pages_per_step = config.backup_pages_per_step as i32;
+    let backup_path = path.clone();
+    let report = db::interact(&pool, move |conn| -> Result<_, ItoError> {
+        let started = Instant::now();
+        let mut dest = Connection::open(&backup_path)?;
+        let backup = Backup::new(conn, &mut dest)?;
+        backup.run_to_completion(pages_per_step, BACKUP_STEP_PAUSE, None)?;
+        let pages_backed_up = backup.progress().pagecount;
+        Ok(BackupReport {
+            pages_backed_up,
+            elapsed_ms: started.elapsed().as_millis(),
+        })
+    })
+    .await;
+    if report.is_err() {
+        // A partial copy would look like a backup.
+        let _ = fs::remove_file(&path);
+    }
+    Ok(Json(report?))
+}
@@ -0,0 +1,278 @@
+use std::{fs, sync::LazyLock, time::Duration};
+
+use anyhow::{anyhow, bail, Context, Result};
+use regex::Regex;
+use rusqlite::Connection;
+use unicode_normalization::UnicodeNormalization;
+use unicode_xid::UnicodeXID;
+use url::Url;
+
+use crate::{
+    config::{AliasGenerator, Config},
+    ssrf::PublicClient,
+};
+
+const BASE62: &[u8; 62] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// The EFF short word list (https://www.eff.org/dice, CC BY 3.0), used when
+/// no `wordlist_path` is configured.
+const EFF_SHORT_WORDLIST: &str = include_str!("assets/eff_short_wordlist.txt");
+
+/// How long a target page has to produce its title.
+const TITLE_FETCH_TIMEOUT: Duration = Duration::from_secs(2);
+/// How much of a target page is searched for its title.
+const TITLE_SEARCH_BYTES: usize = 64 * 1024;
+const TITLE_ALIAS_MAX_LENGTH: usize = 32;
+
+static TITLE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?is)<title[^>]*>(.*?)</title>").expect("title regex is valid"));
+static HTML_ENTITY: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"&#?\w+;").expect("entity regex is valid"));
+
+/// `alias` in NFC, so differently composed spellings of it are the same
+/// alias. Aliases may use any script but only identifier characters and
+/// hyphens, so they stay readable in a URL bar.
+pub fn normalize(alias: &str) -> Result<String> {
+    let alias: String = alias.nfc().collect();
+    if let Some(c) = alias
+        .chars()
+        .find(|&c| !(UnicodeXID::is_xid_continue(c) || c == '-'))
+    {
+        bail!("{c:?} can't be used in an alias");
+    }
+    Ok(alias)
+}
+
+/// A random base62 alias of `length` characters, drawn from the operating
+/// system's CSPRNG so generated aliases can't be predicted or enumerated.
+fn random(length: usize) -> Result<String> {
+    let mut alias = String::with_capacity(length);
+    let mut bytes = [0; 32];
+    while alias.len() < length {
+        getrandom::fill(&mut bytes).map_err(|err| anyhow!("failed to generate alias: {err}"))?;
+        // Bytes past the largest multiple of 62 are dropped so every
+        // character is equally likely.
+        for byte in bytes.iter().filter(|&&byte| byte < 248) {
+            if alias.len() == length {
+                break;
+            }
+            alias.push(BASE62[usize::from(byte % 62)] as char);
+        }
+    }
+    Ok(alias)
+}
+
+/// A random number below `bound`, with every value equally likely.
+fn random_below(bound: usize) -> Result<usize> {
+    let bound = u32::try_from(bound)?;
+    // Values past the largest multiple of `bound` are redrawn.
+    let limit = u32::MAX - u32::MAX % bound;
+    loop {
+        let mut bytes = [0; 4];
+        getrandom::fill(&mut bytes).map_err(|err| anyhow!("failed to generate alias: {err}"))?;
+        let value = u32::from_le_bytes(bytes);
+        if value < limit {
+            return Ok((value % bound) as usize);
+        }
+    }
+}
+
+/// Makes up aliases like `red-table-7` from a list of words.
+pub struct WordAliasGenerator {
+    words: Vec<String>,
+    word_count: usize,
+}
+
+impl WordAliasGenerator {
+    /// Reads one word per line. Lines of EFF dice lists, like `11111 abacus`,
+    /// contribute their last field.
+    fn parse(list: &str, word_count: usize) -> Result<Self> {
+        let words: Vec<String> = list
+            .lines()
+            .filter_map(|line| line.split_whitespace().last())
+            .map(str::to_lowercase)
+            .collect();
+        if let Some(word) = words
+            .iter()
+            .find(|word| !word.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'))
+        {
+            bail!("word {word:?} can't be used in an alias");
+        }
+        if words.is_empty() {
+            bail!("the word list is empty");
+        }
+        Ok(Self { words, word_count })
+    }
+
+    fn generate(&self) -> Result<String> {
+        let mut parts = Vec::with_capacity(self.word_count + 1);
+        for _ in 0..self.word_count {
+            parts.push(self.words[random_below(self.words.len())?].clone());
+        }
+        parts.push(random_below(10)?.to_string());
+        Ok(parts.join("-"))
+    }
+}
+
+/// Derives aliases from target pages' titles, for `Config::auto_title_alias`.
+pub struct TitleAliases {
+    client: PublicClient,
+}
+
+impl TitleAliases {
+    pub fn new(config: &Config) -> Result<Self> {
+        Ok(Self {
+            client: PublicClient::new(config, TITLE_FETCH_TIMEOUT)?,
+        })
+    }
+
+    /// An alias made from the title of the page at `url`, if it is an HTML
+    /// page with a usable title. Failures just mean no alias.
+    pub async fn suggest(&self, url: &Url) -> Option<String> {
+        let mut response = self.client.get(url).ok()?.send().await.ok()?;
+        let is_html = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|content_type| content_type.starts_with("text/html"));
+        if !response.status().is_success() || !is_html {
+            return None;
+        }
+        let mut page = Vec::new();
+        while page.len() < TITLE_SEARCH_BYTES {
+            match response.chunk().await.ok()? {
+                Some(chunk) => page.extend_from_slice(&chunk),
+                None => break,
+            }
+        }
+        let page = String::from_utf8_lossy(&page);
+        title_slug(&TITLE.captures(&page)?[1])
+    }
+}
+
+/// `title` in lowercase ASCII letters and digits separated by single hyphens,
+/// cut to `TITLE_ALIAS_MAX_LENGTH`.
+fn title_slug(title: &str) -> Option<String> {
+    let title = HTML_ENTITY.replace_all(title, " ");
+    let words: Vec<String> = title
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_ascii_lowercase)
+        .collect();
+    let mut slug = words.join("-");
+    slug.truncate(TITLE_ALIAS_MAX_LENGTH);
+    let slug = slug.trim_end_matches('-');
+    (!slug.is_empty()).then(|| slug.to_string())
+}
+
+/// `base`, or `base-2`, `base-3` and so on if that is taken, trying up to
+/// `max_retries` suffixes.
+pub fn unused_with_suffix(conn: &Connection, base: &str, max_retries: u32) -> Result<String> {
+    for n in 1..=max_retries + 1 {
+        let alias = match n {
+            1 => base.to_string(),
+            n => format!("{base}-{n}"),
+        };
+        let taken: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM links WHERE alias = ? COLLATE NOCASE)",
+            [&alias],
+            |row| row.get(0),
+        )?;
+        if !taken {
+            return Ok(alias);
+        }
+    }
+    bail!(
+        "no unused alias like {base} found after {} attempts",
+        max_retries + 1
+    )
+}
+
+/// Makes up aliases for links created without one, as configured by
+/// `Config::alias_generator`.
+pub enum Generator {
+    Random { length: usize },
+    Words(WordAliasGenerator),
+}
+
+impl Generator {
+    pub fn from_config(config: &Config) -> Result<Self> {
+        Ok(match config.alias_generator {
+            AliasGenerator::Random => Generator::Random {
+                length: config.alias_length,
+            },
+            AliasGenerator::Words => {
+                let words = match &config.wordlist_path {
+                    Some(path) => {
+                        let list = fs::read_to_string(path)
+                            .with_context(|| format!("failed to read {}", path.display()))?;
+                        WordAliasGenerator::parse(&list, config.alias_word_count)
+                            .with_context(|| format!("invalid word list {}", path.display()))?
+                    }
+                    None => WordAliasGenerator::parse(EFF_SHORT_WORDLIST, config.alias_word_count)?,
+                };
+                Generator::Words(words)
+            }
+        })
+    }
+
+    fn generate(&self) -> Result<String> {
+        match self {
+            Generator::Random { length } => random(*length),
+            Generator::Words(words) => words.generate(),
+        }
+    }
+
+    /// An alias that no link uses yet, trying again on a collision up to
+    /// `max_retries` times.
+    pub fn unused(&self, conn: &Connection, max_retries: u32) -> Result<String> {
+        for _ in 0..=max_retries {
+            let alias = self.generate()?;
+            let taken: bool = conn.query_row(
+                "SELECT EXISTS (SELECT 1 FROM links WHERE alias = ? COLLATE NOCASE)",
+                [&alias],
+                |row| row.get(0),
+            )?;
+            if !taken {
+                return Ok(alias);
+            }
+        }
+        bail!("no unused alias found after {} attempts", max_retries + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_accepts_identifiers_in_any_script() {
+        for alias in ["my-link_2", "привет", "中文链接", "مرحبا"] {
+            assert_eq!(normalize(alias).unwrap(), alias);
+        }
+    }
+
+    #[test]
+    fn normalize_composes_to_nfc() {
+        assert_eq!(normalize("cafe\u{301}").unwrap(), "caf\u{e9}");
+    }
+
+    #[test]
+    fn normalize_rejects_other_characters() {
+        for alias in ["a b", "a.b", "a/b", "a?b"] {
+            assert!(normalize(alias).is_err(), "{alias:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn title_slug_keeps_ascii_words() {
+        assert_eq!(
+            title_slug("  Rust &amp; Cargo: The Book!  ").as_deref(),
+            Some("rust-cargo-the-book")
+        );
+        assert_eq!(title_slug("!!!"), None);
+        let slug = title_slug(&"word ".repeat(20)).unwrap();
+        assert!(slug.len() <= TITLE_ALIAS_MAX_LENGTH);
+        assert!(!slug.ends_with('-'));
+    }
+}
@@ -0,0 +1,64 @@
+use anyhow::{bail, Result};
+
+/// The longest analytics snippet a link may have.
+pub const MAX_SNIPPET_CHARS: usize = 500;
+
+/// Rejects snippets that are too long, that could close the `<script>` tag
+/// they are put in, or whose brackets don't balance. This is no more than a
+/// check for obvious mistakes: any snippet that passes runs as written.
+pub fn validate_snippet(snippet: &str) -> Result<()> {
+    if snippet.chars().count() > MAX_SNIPPET_CHARS {
+        bail!("analytics snippets can be at most {MAX_SNIPPET_CHARS} characters");
+    }
+    if snippet.contains("</") || snippet.contains("<!--") {
+        bail!("analytics snippets can't contain HTML tags or comments");
+    }
+    let mut open = Vec::new();
+    let mut quote = None;
+    let mut chars = snippet.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(_), '\\') => {
+                chars.next();
+            }
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"' | '`') => quote = Some(c),
+            (None, '(' | '[' | '{') => open.push(c),
+            (None, ')' | ']' | '}') => {
+                let expected = match c {
+                    ')' => '(',
+                    ']' => '[',
+                    _ => '{',
+                };
+                if open.pop() != Some(expected) {
+                    bail!("unbalanced {c:?} in analytics snippet");
+                }
+            }
+            (None, _) => {}
+        }
+    }
+    if quote.is_some() {
+        bail!("unterminated string in analytics snippet");
+    }
+    if let Some(c) = open.pop() {
+        bail!("unclosed {c:?} in analytics snippet");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_snippet_checks_brackets_outside_strings() {
+        assert!(validate_snippet("gtag('event', 'click', { id: ')' });").is_ok());
+        assert!(validate_snippet("gtag('event', { id: 1 );").is_err());
+        assert!(validate_snippet("gtag('event'").is_err());
+        assert!(validate_snippet("f('unterminated)").is_err());
+        assert!(validate_snippet("a('</script><script>b()')").is_err());
+        assert!(validate_snippet("a('</SCRIPT >')").is_err());
+        assert!(validate_snippet(&"x;".repeat(MAX_SNIPPET_CHARS)).is_err());
+    }
+}
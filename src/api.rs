@@ -0,0 +1,598 @@
+use std::{
+    io,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail};
+use axum::{
+    body::{Bytes, StreamBody},
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::{NaiveDate, NaiveTime};
+use rusqlite::{params, Connection, OptionalExtension, Row, Statement, ToSql, TransactionBehavior};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use url::Url;
+
+use crate::{
+    alias,
+    auth::Claims,
+    check_target_domain,
+    config::{Config, TimestampPrecision},
+    db, handle_sqlite_err,
+    keys::SigningKeys,
+    metrics::{self, QueryType},
+    pagination, parse_response_content_type, redirect_loop_error, redirects_back_to,
+    remaining_clicks, render_markdown,
+    thumbnails::Thumbnails,
+    users, ItoError, ItoJsonError, ItoPool, ReadPool,
+};
+
+/// A link as returned by the JSON API.
+#[derive(Serialize)]
+pub struct ApiLink {
+    id: i64,
+    alias: String,
+    target_url: Url,
+    description: Option<String>,
+    description_html: Option<String>,
+    tags: Vec<String>,
+    created_at: Option<String>,
+    expires_at: Option<String>,
+    click_count: i64,
+    remaining_clicks: Option<u64>,
+    og_title: Option<String>,
+    og_description: Option<String>,
+    /// When `og_title` and `og_description` were last fetched.
+    metadata_refreshed_at: Option<String>,
+}
+
+/// The columns `api_link` reads, in order.
+const API_LINK_COLUMNS: &str = "id, alias, target_url, description, created_at, expires_at,
+    click_count, max_clicks, og_title, og_description, metadata_refreshed_at";
+
+pub const LINK_TAGS_SQL: &str =
+    "SELECT tags.name FROM link_tags JOIN tags ON tags.id = link_tags.tag_id
+    WHERE link_tags.link_id = ? ORDER BY tags.name";
+
+/// Builds an `ApiLink` from a row of `API_LINK_COLUMNS`, looking up its tags
+/// with a prepared `LINK_TAGS_SQL`.
+fn api_link(
+    row: &Row,
+    tags: &mut Statement,
+    precision: TimestampPrecision,
+) -> rusqlite::Result<ApiLink> {
+    let id = row.get(0)?;
+    let description: Option<String> = row.get(3)?;
+    Ok(ApiLink {
+        id,
+        alias: row.get(1)?,
+        target_url: row.get(2)?,
+        description_html: description.as_deref().map(render_markdown),
+        description,
+        tags: tags
+            .query_map([id], |row| row.get(0))?
+            .collect::<Result<_, _>>()?,
+        created_at: row
+            .get::<_, Option<String>>(4)?
+            .map(|created_at| precision.truncate(created_at)),
+        expires_at: row.get(5)?,
+        click_count: row.get(6)?,
+        remaining_clicks: remaining_clicks(row.get(7)?, row.get(6)?),
+        og_title: row.get(8)?,
+        og_description: row.get(9)?,
+        metadata_refreshed_at: row.get(10)?,
+    })
+}
+
+/// Loads the link with `alias`, matched case-insensitively.
+pub fn load_link(
+    conn: &Connection,
+    alias: &str,
+    precision: TimestampPrecision,
+) -> rusqlite::Result<ApiLink> {
+    let mut tags = conn.prepare(LINK_TAGS_SQL)?;
+    conn.query_row(
+        &format!("SELECT {API_LINK_COLUMNS} FROM links WHERE alias = ? COLLATE NOCASE"),
+        [alias],
+        |row| api_link(row, &mut tags, precision),
+    )
+}
+
+/// Narrows a listing or export down to the links tagged `tag`, or one of its
+/// descendant tags, that were created between `from` and `to` inclusive.
+#[derive(Default, Deserialize)]
+pub struct LinkFilter {
+    tag: Option<String>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+}
+
+/// The `WITH` clause `LINK_FILTER_CONDITION` needs.
+pub const LINK_FILTER_TAGS_SQL: &str = "WITH RECURSIVE filter_tags (id) AS (
+        SELECT id FROM tags WHERE name = :tag
+        UNION
+        SELECT tags.id FROM tags JOIN filter_tags ON tags.parent_id = filter_tags.id
+    )";
+
+/// Whether a row of `links` passes a `LinkFilter`, with the named parameters
+/// from `LinkFilter::sql_params`.
+pub const LINK_FILTER_CONDITION: &str = "(:tag IS NULL OR EXISTS (
+        SELECT 1 FROM link_tags
+        WHERE link_tags.link_id = links.id AND link_tags.tag_id IN (SELECT id FROM filter_tags)
+    ))
+    AND (:from IS NULL OR created_at >= :from)
+    AND (:to IS NULL OR created_at < :to)";
+
+impl LinkFilter {
+    fn tag(&self) -> Option<&str> {
+        self.tag.as_deref().filter(|tag| !tag.is_empty())
+    }
+
+    /// Values for the named parameters in `LINK_FILTER_CONDITION`.
+    pub fn sql_params(&self) -> [(&'static str, Option<String>); 3] {
+        let midnight =
+            |date: NaiveDate| db::format_timestamp(date.and_time(NaiveTime::MIN).and_utc());
+        [
+            (":tag", self.tag().map(str::to_string)),
+            (":from", self.from.map(midnight)),
+            // Up to the start of the next day, so `to` is included.
+            (":to", self.to.and_then(|to| to.succ_opt()).map(midnight)),
+        ]
+    }
+
+    /// A download filename like `links-tag-work-from-2024-01-01.csv`, which
+    /// says what the filter let through.
+    pub fn filename(&self, base: &str, extension: &str) -> String {
+        let mut name = base.to_string();
+        if let Some(tag) = self.tag() {
+            let tag: String = tag
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() || c == '-' {
+                        c
+                    } else {
+                        '_'
+                    }
+                })
+                .collect();
+            name.push_str(&format!("-tag-{tag}"));
+        }
+        if let Some(from) = self.from {
+            name.push_str(&format!("-from-{from}"));
+        }
+        if let Some(to) = self.to {
+            name.push_str(&format!("-to-{to}"));
+        }
+        format!("{name}.{extension}")
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PageParams {
+    limit: Option<u32>,
+    cursor: Option<String>,
+}
+
+/// A page of links in id order, with the cursor for the next one.
+#[derive(Serialize)]
+pub struct LinkPage {
+    links: Vec<ApiLink>,
+    next_cursor: Option<String>,
+    has_more: bool,
+}
+
+/// Streams every link as a JSON array, sending each one as soon as it has
+/// been read. With `?limit=` or `?cursor=` it returns one `LinkPage`
+/// instead.
+pub async fn list_links(
+    State(ReadPool(pool)): State<ReadPool>,
+    State(config): State<Arc<Config>>,
+    State(keys): State<Arc<SigningKeys>>,
+    Query(filter): Query<LinkFilter>,
+    Query(page): Query<PageParams>,
+) -> Result<Response, ItoJsonError> {
+    if page.limit.is_some() || page.cursor.is_some() {
+        return Ok(Json(list_page(&pool, &config, &keys, filter, page).await?).into_response());
+    }
+    let deadline = Instant::now() + Duration::from_secs(config.streaming_timeout_secs);
+    let conn = pool.get().await?;
+    Ok((
+        [(header::CONTENT_TYPE, "application/json")],
+        stream_links(
+            conn,
+            Framing::JsonArray,
+            filter,
+            config.timestamp_precision,
+            deadline,
+        ),
+    )
+        .into_response())
+}
+
+/// Pages by id rather than by offset, so each page is a quick index range
+/// scan and links created or deleted meanwhile don't shift later pages.
+async fn list_page(
+    pool: &ItoPool,
+    config: &Config,
+    keys: &SigningKeys,
+    filter: LinkFilter,
+    page: PageParams,
+) -> Result<LinkPage, ItoError> {
+    let after = page
+        .cursor
+        .as_deref()
+        .map(|cursor| pagination::decode_cursor(keys, cursor))
+        .transpose()
+        .map_err(|err| ItoError {
+            err,
+            sc: StatusCode::BAD_REQUEST,
+        })?;
+    let limit = page
+        .limit
+        .unwrap_or(pagination::MAX_PAGE_SIZE)
+        .clamp(1, pagination::MAX_PAGE_SIZE);
+    let precision = config.timestamp_precision;
+    let mut links = db::interact(pool, move |conn| -> Result<_, ItoError> {
+        let mut tags = conn.prepare(LINK_TAGS_SQL)?;
+        let mut statement = conn.prepare(&format!(
+            "{LINK_FILTER_TAGS_SQL}
+            SELECT {API_LINK_COLUMNS} FROM links
+            WHERE (:after IS NULL OR id > :after) AND {LINK_FILTER_CONDITION}
+            ORDER BY id LIMIT :limit"
+        ))?;
+        // One more than a page, to tell whether there is another.
+        let fetch = limit + 1;
+        let filter_params = filter.sql_params();
+        let mut params: Vec<(&str, &dyn ToSql)> = vec![(":after", &after), (":limit", &fetch)];
+        params.extend(
+            filter_params
+                .iter()
+                .map(|(name, value)| (*name, value as &dyn ToSql)),
+        );
+        let mut rows = statement.query(params.as_slice())?;
+        let mut links = Vec::new();
+        while let Some(row) = rows.next()? {
+            links.push(api_link(row, &mut tags, precision)?);
+        }
+        Ok(links)
+    })
+    .await?;
+    let has_more = links.len() > limit as usize;
+    links.truncate(limit as usize);
+    let next_cursor = links
+        .last()
+        .filter(|_| has_more)
+        .map(|link| pagination::encode_cursor(keys, link.id));
+    Ok(LinkPage {
+        links,
+        next_cursor,
+        has_more,
+    })
+}
+
+/// Streams every link as newline-delimited JSON, one object per line, as a
+/// download for tools like `jq` or DuckDB.
+pub async fn export_ndjson(
+    State(ReadPool(pool)): State<ReadPool>,
+    State(config): State<Arc<Config>>,
+    Query(filter): Query<LinkFilter>,
+) -> Result<impl IntoResponse, ItoError> {
+    export(pool, &config, filter, Framing::Ndjson).await
+}
+
+/// Streams every link as CSV with the columns `import_csv` reads, so an
+/// export can be imported into another instance.
+pub async fn export_csv(
+    State(ReadPool(pool)): State<ReadPool>,
+    State(config): State<Arc<Config>>,
+    Query(filter): Query<LinkFilter>,
+) -> Result<impl IntoResponse, ItoError> {
+    export(pool, &config, filter, Framing::Csv).await
+}
+
+async fn export(
+    pool: ItoPool,
+    config: &Config,
+    filter: LinkFilter,
+    framing: Framing,
+) -> Result<impl IntoResponse, ItoError> {
+    let (content_type, extension) = match framing {
+        Framing::Csv => ("text/csv; charset=utf-8", "csv"),
+        _ => ("application/x-ndjson", "ndjson"),
+    };
+    let disposition = format!(
+        "attachment; filename=\"{}\"",
+        filter.filename("links", extension)
+    );
+    let deadline = Instant::now() + Duration::from_secs(config.streaming_timeout_secs);
+    let conn = pool.get().await?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        stream_links(conn, framing, filter, config.timestamp_precision, deadline),
+    ))
+}
+
+/// How the links in a streamed listing are delimited.
+#[derive(Clone, Copy)]
+enum Framing {
+    JsonArray,
+    Ndjson,
+    Csv,
+}
+
+fn stream_links(
+    conn: deadpool_sqlite::Object,
+    framing: Framing,
+    filter: LinkFilter,
+    precision: TimestampPrecision,
+    deadline: Instant,
+) -> StreamBody<ReceiverStream<io::Result<Bytes>>> {
+    let (tx, rx) = mpsc::channel(16);
+    tokio::spawn(async move {
+        conn.interact(move |conn| {
+            if let Err(err) = write_links(conn, framing, &filter, precision, deadline, &tx) {
+                let _ = tx.blocking_send(Err(io::Error::other(err.to_string())));
+            }
+        })
+        .await
+    });
+    StreamBody::new(ReceiverStream::new(rx))
+}
+
+fn write_links(
+    conn: &Connection,
+    framing: Framing,
+    filter: &LinkFilter,
+    precision: TimestampPrecision,
+    deadline: Instant,
+    tx: &mpsc::Sender<io::Result<Bytes>>,
+) -> anyhow::Result<()> {
+    // Returns early, without an error, once the client has gone away.
+    let send = |chunk: Vec<u8>| tx.blocking_send(Ok(Bytes::from(chunk))).is_ok();
+    let header = match framing {
+        Framing::JsonArray => b"[".to_vec(),
+        Framing::Csv => b"alias,target_url,description\n".to_vec(),
+        Framing::Ndjson => Vec::new(),
+    };
+    if !header.is_empty() && !send(header) {
+        return Ok(());
+    }
+    let mut tags = conn.prepare(LINK_TAGS_SQL)?;
+    let mut statement = conn.prepare(&format!(
+        "{LINK_FILTER_TAGS_SQL}
+        SELECT {API_LINK_COLUMNS} FROM links WHERE {LINK_FILTER_CONDITION} ORDER BY id"
+    ))?;
+    let params = filter.sql_params();
+    let params: Vec<(&str, &dyn ToSql)> = params
+        .iter()
+        .map(|(name, value)| (*name, value as &dyn ToSql))
+        .collect();
+    let mut rows = statement.query(params.as_slice())?;
+    let mut first = true;
+    while let Some(row) = rows.next()? {
+        if Instant::now() >= deadline {
+            bail!("listing took longer than ITO_STREAMING_TIMEOUT_SECS");
+        }
+        let mut chunk = match framing {
+            Framing::JsonArray if !first => b",".to_vec(),
+            _ => Vec::new(),
+        };
+        first = false;
+        let link = api_link(row, &mut tags, precision)?;
+        match framing {
+            Framing::Csv => {
+                let mut writer = csv::Writer::from_writer(&mut chunk);
+                writer.serialize((&link.alias, link.target_url.as_str(), &link.description))?;
+                writer.flush()?;
+            }
+            Framing::Ndjson => {
+                serde_json::to_writer(&mut chunk, &link)?;
+                chunk.push(b'\n');
+            }
+            Framing::JsonArray => serde_json::to_writer(&mut chunk, &link)?,
+        }
+        if !send(chunk) {
+            return Ok(());
+        }
+    }
+    if let Framing::JsonArray = framing {
+        send(b"]".to_vec());
+    }
+    Ok(())
+}
+
+/// Replaces the tags on `link_id`, creating any tags that don't exist yet.
+pub fn set_tags(conn: &Connection, link_id: i64, tags: &[String]) -> rusqlite::Result<()> {
+    conn.execute("DELETE FROM link_tags WHERE link_id = ?", [link_id])?;
+    for tag in tags
+        .iter()
+        .map(|tag| tag.trim())
+        .filter(|tag| !tag.is_empty())
+    {
+        conn.execute("INSERT OR IGNORE INTO tags (name) VALUES (?)", [tag])?;
+        conn.execute(
+            "INSERT OR IGNORE INTO link_tags (link_id, tag_id)
+            SELECT ?1, id FROM tags WHERE name = ?2",
+            params![link_id, tag],
+        )?;
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct ByUrlParams {
+    url: Url,
+}
+
+pub const LINKS_BY_URL_SQL: &str = "SELECT alias FROM links WHERE target_url = ? ORDER BY id";
+
+/// Lists every link pointing at exactly `url`.
+pub async fn links_by_url(
+    State(ReadPool(pool)): State<ReadPool>,
+    State(config): State<Arc<Config>>,
+    Query(params): Query<ByUrlParams>,
+) -> Result<Json<Vec<ApiLink>>, ItoJsonError> {
+    let links = db::interact(&pool, move |conn| -> Result<_, ItoError> {
+        let mut statement = conn.prepare(LINKS_BY_URL_SQL)?;
+        let aliases = statement
+            .query_map([&params.url], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        if aliases.is_empty() {
+            return Err(ItoError {
+                err: anyhow!("no link points to {}", params.url),
+                sc: StatusCode::NOT_FOUND,
+            });
+        }
+        Ok(aliases
+            .iter()
+            .map(|alias| load_link(conn, alias, config.timestamp_precision))
+            .collect::<Result<_, _>>()?)
+    })
+    .await?;
+    Ok(Json(links))
+}
+
+#[derive(Serialize)]
+pub struct CheckResult {
+    found: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    alias: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    short_url: Option<Url>,
+}
+
+/// Whether some link already points at exactly `url`, for browser extensions.
+/// Both outcomes are a 200, and answers may be cached for a minute.
+pub async fn check_url(
+    State(ReadPool(pool)): State<ReadPool>,
+    State(config): State<Arc<Config>>,
+    Query(params): Query<ByUrlParams>,
+) -> Result<impl IntoResponse, ItoJsonError> {
+    let alias: Option<String> = db::interact(&pool, move |conn| {
+        conn.query_row(
+            "SELECT alias FROM links
+            WHERE target_url = ? AND access_password_hash IS NULL
+            ORDER BY id LIMIT 1",
+            [&params.url],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(ItoError::from)
+    })
+    .await?;
+    let result = CheckResult {
+        found: alias.is_some(),
+        short_url: alias
+            .as_deref()
+            .map(|alias| config.short_url(alias))
+            .transpose()?,
+        alias,
+    };
+    Ok((
+        [(header::CACHE_CONTROL, "public, max-age=60")],
+        Json(result),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct UpsertLinkInput {
+    target_url: Url,
+    description: Option<String>,
+    tags: Option<Vec<String>>,
+    response_content_type: Option<String>,
+}
+
+/// Creates the link `alias` for the token's user, or points it at a new
+/// target if it already exists and they may modify it. Omitted fields keep
+/// their current values on update.
+pub async fn upsert_link(
+    State(pool): State<ItoPool>,
+    State(config): State<Arc<Config>>,
+    State(thumbnails): State<Option<Arc<Thumbnails>>>,
+    Extension(claims): Extension<Claims>,
+    Path(alias): Path<String>,
+    Json(input): Json<UpsertLinkInput>,
+) -> Result<(StatusCode, Json<ApiLink>), ItoJsonError> {
+    check_target_domain(&config, &input.target_url)?;
+    let alias = alias::normalize(&alias).map_err(|err| ItoError {
+        err,
+        sc: StatusCode::BAD_REQUEST,
+    })?;
+    let response_content_type = match &input.response_content_type {
+        Some(content_type) => parse_response_content_type(content_type)?,
+        None => None,
+    };
+    let target_url = input.target_url.clone();
+    let (existed, link) = db::interact(&pool, move |conn| -> Result<_, ItoError> {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let user = users::token_user(&tx, &claims)?;
+        let owner_id: Option<Option<i64>> = tx
+            .query_row(
+                "SELECT user_id FROM links WHERE alias = ? COLLATE NOCASE",
+                [&alias],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let existed = owner_id.is_some();
+        if owner_id.is_some_and(|owner_id| !user.can_modify(owner_id)) {
+            return Err(ItoError {
+                err: anyhow!("link {alias} belongs to another user"),
+                sc: StatusCode::FORBIDDEN,
+            });
+        }
+        if redirects_back_to(&tx, &config, &alias, &input.target_url)? {
+            return Err(redirect_loop_error(&alias));
+        }
+        metrics::time_query(QueryType::InsertLink, || {
+            tx.execute(
+                "INSERT INTO links
+                    (alias, target_url, description, response_content_type, user_id, created_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))
+                ON CONFLICT (alias COLLATE NOCASE) DO UPDATE SET
+                    target_url = excluded.target_url,
+                    description = coalesce(excluded.description, description),
+                    response_content_type =
+                        coalesce(excluded.response_content_type, response_content_type)",
+                params![
+                    alias,
+                    input.target_url,
+                    input.description,
+                    response_content_type,
+                    user.id
+                ],
+            )
+        })
+        .map_err(handle_sqlite_err)?;
+        if let Some(tags) = input.tags {
+            let link_id = tx.query_row(
+                "SELECT id FROM links WHERE alias = ? COLLATE NOCASE",
+                [&alias],
+                |row| row.get(0),
+            )?;
+            set_tags(&tx, link_id, &tags)?;
+        }
+        let link = load_link(&tx, &alias, config.timestamp_precision)?;
+        tx.commit()?;
+        Ok((existed, link))
+    })
+    .await?;
+
+    let sc = if existed {
+        StatusCode::OK
+    } else {
+        if let Some(thumbnails) = &thumbnails {
+            thumbnails.capture(pool, link.id, target_url);
+        }
+        StatusCode::CREATED
+    };
+    Ok((sc, Json(link)))
+}
@@ -0,0 +1,10 @@
+// Opens ito's create form in a popup, filled in with the current page.
+(function () {
+    var baseUrl = ITO_BASE_URL;
+    var params = new URLSearchParams({
+        target_url: location.href,
+        alias: "",
+        description: document.title,
+    });
+    window.open(baseUrl + "/?" + params, "ito", "popup,width=640,height=720");
+})();
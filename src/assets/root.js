@@ -0,0 +1,62 @@
+document.querySelectorAll(".copy-short-url").forEach((button) => {
+    button.addEventListener("click", () => {
+        navigator.clipboard.writeText(button.dataset.url)
+            .then(() => {
+                var tooltip = button.nextElementSibling;
+                tooltip.hidden = false;
+                setTimeout(() => { tooltip.hidden = true; }, 2000);
+            });
+    });
+});
+
+document.querySelectorAll(".delete-link").forEach((form) => {
+    form.addEventListener("submit", (e) => {
+        e.preventDefault();
+        var id = form['id'].value;
+        fetch("/links/" + id, {
+            method: 'DELETE',
+            headers: { 'X-CSRF-Token': document.body.dataset.csrfToken },
+        })
+            .then((response) => {
+                if (response.ok) {
+                    window.location.replace("/");
+                } else {
+                    response.text().then(alert);
+                }
+            });
+    });
+});
+
+// Click counts are fetched as each link scrolls into view, so long lists
+// don't count every link's clicks up front.
+var clickCounts = new IntersectionObserver((entries) => {
+    entries.filter((entry) => entry.isIntersecting).forEach((entry) => {
+        var span = entry.target;
+        clickCounts.unobserve(span);
+        fetch(span.dataset.url)
+            .then((response) => response.ok ? response.text() : "?")
+            .then((count) => { span.textContent = count; });
+    });
+});
+document.querySelectorAll(".click-count").forEach((span) => clickCounts.observe(span));
+
+document.querySelectorAll(".recheck-link").forEach((button) => {
+    button.addEventListener("click", () => {
+        button.disabled = true;
+        fetch("/links/" + button.dataset.id + "/recheck", {
+            method: 'POST',
+            headers: { 'X-CSRF-Token': document.body.dataset.csrfToken },
+        })
+            .then((response) => response.json())
+            .then((result) => {
+                if (result.error) {
+                    alert(result.error);
+                    return;
+                }
+                var badge = button.closest("li").querySelector(".reachability");
+                badge.style.color = result.color;
+                badge.title = result.label;
+            })
+            .finally(() => { button.disabled = false; });
+    });
+});
@@ -0,0 +1,18 @@
+use rusqlite::{params, Connection};
+use serde_json::Value;
+
+/// Appends an entry to the audit log. `details` is stored as JSON.
+pub fn record(
+    conn: &Connection,
+    action: &str,
+    actor_user_id: Option<i64>,
+    link_id: Option<i64>,
+    details: Value,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO audit_log (action, actor_user_id, link_id, details, created_at)
+        VALUES (?1, ?2, ?3, ?4, strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))",
+        params![action, actor_user_id, link_id, details.to_string()],
+    )?;
+    Ok(())
+}
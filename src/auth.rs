@@ -0,0 +1,167 @@
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::anyhow;
+use axum::{
+    extract::{OriginalUri, State},
+    http::{header, HeaderMap, Method, Request, StatusCode},
+    middleware::Next,
+    response::Response,
+    Json,
+};
+use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+
+use crate::{config::Config, ItoError, ItoJsonError};
+
+const DEFAULT_TOKEN_TTL_SECS: u64 = 24 * 60 * 60;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    Read,
+    Write,
+    Admin,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    pub exp: u64,
+    pub scopes: Vec<Scope>,
+}
+
+/// Rejects requests without a valid bearer token carrying the scope the route
+/// requires: `admin` under `/admin`, `read` for GET and `write` for anything
+/// else. On success the token's `Claims` are added to the request extensions.
+/// Without `ITO_JWT_SECRET` no token can be valid, so every request is refused.
+pub async fn require_scope<B>(
+    State(config): State<Arc<Config>>,
+    OriginalUri(uri): OriginalUri,
+    mut req: Request<B>,
+    next: Next<B>,
+) -> Result<Response, ItoJsonError> {
+    let Some(secret) = &config.jwt_secret else {
+        return Err(error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "the API is disabled until ITO_JWT_SECRET is set",
+        )
+        .into());
+    };
+    let token = bearer_token(req.headers())
+        .ok_or_else(|| error(StatusCode::UNAUTHORIZED, "missing bearer token"))?;
+    let claims = jsonwebtoken::decode::<Claims>(
+        token,
+        &DecodingKey::from_secret(secret.as_bytes()),
+        &Validation::default(),
+    )
+    .map_err(|err| ItoError {
+        err: err.into(),
+        sc: StatusCode::UNAUTHORIZED,
+    })?
+    .claims;
+
+    let required = required_scope(req.method(), uri.path());
+    if !claims.scopes.contains(&required) {
+        return Err(error(StatusCode::FORBIDDEN, "token is missing the required scope").into());
+    }
+    req.extensions_mut().insert(claims);
+    Ok(next.run(req).await)
+}
+
+/// The subject of the request's bearer token, if it carries a valid one.
+pub fn token_subject(config: &Config, headers: &HeaderMap) -> Option<String> {
+    token_claims(config, headers).map(|claims| claims.sub)
+}
+
+/// The claims of the request's bearer token, if it carries a valid one.
+pub fn token_claims(config: &Config, headers: &HeaderMap) -> Option<Claims> {
+    let secret = config.jwt_secret.as_ref()?;
+    jsonwebtoken::decode::<Claims>(
+        bearer_token(headers)?,
+        &DecodingKey::from_secret(secret.as_bytes()),
+        &Validation::default(),
+    )
+    .ok()
+    .map(|token| token.claims)
+}
+
+fn required_scope(method: &Method, path: &str) -> Scope {
+    if path == "/admin" || path.starts_with("/admin/") {
+        Scope::Admin
+    } else if method == Method::GET || method == Method::HEAD {
+        Scope::Read
+    } else {
+        Scope::Write
+    }
+}
+
+#[derive(Deserialize)]
+pub struct TokenRequest {
+    sub: String,
+    scopes: Vec<Scope>,
+    ttl_secs: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct TokenResponse {
+    token: String,
+    exp: u64,
+}
+
+/// Issues a new token to callers presenting the master secret as their bearer token.
+pub async fn issue_token(
+    State(config): State<Arc<Config>>,
+    headers: HeaderMap,
+    Json(input): Json<TokenRequest>,
+) -> Result<Json<TokenResponse>, ItoJsonError> {
+    let (Some(secret), Some(master_secret)) = (&config.jwt_secret, &config.jwt_master_secret)
+    else {
+        return Err(error(StatusCode::NOT_FOUND, "token issuance is not configured").into());
+    };
+    let authorized = bearer_token(&headers)
+        .is_some_and(|token| constant_time_eq(token.as_bytes(), master_secret.as_bytes()));
+    if !authorized {
+        return Err(error(StatusCode::UNAUTHORIZED, "invalid master secret").into());
+    }
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let Some(exp) = now.checked_add(input.ttl_secs.unwrap_or(DEFAULT_TOKEN_TTL_SECS)) else {
+        return Err(error(StatusCode::BAD_REQUEST, "ttl_secs is too large").into());
+    };
+    let claims = Claims {
+        sub: input.sub,
+        exp,
+        scopes: input.scopes,
+    };
+    let token = jsonwebtoken::encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(secret.as_bytes()),
+    )?;
+    Ok(Json(TokenResponse {
+        token,
+        exp: claims.exp,
+    }))
+}
+
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn error(sc: StatusCode, msg: &'static str) -> ItoError {
+    ItoError {
+        err: anyhow!(msg),
+        sc,
+    }
+}
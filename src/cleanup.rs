@@ -0,0 +1,53 @@
+use std::time::Duration;
+
+use rusqlite::params;
+use serde_json::json;
+
+use crate::{audit, db, ItoPool};
+
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Every hour, deletes links created more than `after_days` days ago that
+/// have never been clicked. Links with a `notify_email` or `max_clicks` were
+/// set up with some care, so they are kept however unused they are.
+pub async fn delete_unclicked_periodically(pool: ItoPool, after_days: u64) {
+    let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(err) = delete_unclicked(&pool, after_days).await {
+            tracing::warn!("failed to clean up unclicked links: {err:#}");
+        }
+    }
+}
+
+async fn delete_unclicked(pool: &ItoPool, after_days: u64) -> anyhow::Result<()> {
+    db::interact(pool, move |conn| {
+        let tx = conn.transaction()?;
+        let links = tx
+            .prepare(
+                "SELECT id, alias FROM links
+                WHERE click_count = 0
+                    AND created_at < strftime('%Y-%m-%dT%H:%M:%SZ', 'now', ?1)
+                    AND notify_email IS NULL
+                    AND max_clicks IS NULL",
+            )?
+            .query_map([format!("-{after_days} days")], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        for (link_id, alias) in links {
+            tracing::info!("deleting {alias}, unclicked for over {after_days} days");
+            tx.execute("DELETE FROM links WHERE id = ?", params![link_id])?;
+            audit::record(
+                &tx,
+                "delete_link",
+                None,
+                Some(link_id),
+                json!({ "alias": alias, "reason": "unclicked" }),
+            )?;
+        }
+        tx.commit()?;
+        anyhow::Ok(())
+    })
+    .await
+}
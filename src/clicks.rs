@@ -0,0 +1,798 @@
+use std::{
+    io,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Result};
+use axum::{
+    body::{Bytes, StreamBody},
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, Query, State,
+    },
+    http::{header, HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, Datelike, Days, NaiveDate, TimeDelta, Timelike, Utc};
+use chrono_tz::Tz;
+use hmac::{Hmac, Mac};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::{
+    config::Config,
+    db,
+    metrics::{self, QueryType},
+    parse_optional_timestamp, ItoError, ItoJsonError, ItoPool, ReadPool,
+};
+
+const CSV_ROWS_PER_CHUNK: usize = 256;
+const WS_PING_INTERVAL: Duration = Duration::from_secs(30);
+const ROLLUP_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Click stats covering more days than this are served from `click_rollups`.
+const MAX_RAW_STATS_DAYS: u32 = 7;
+
+/// A recorded click, as broadcast to `/ws/clicks` subscribers.
+#[derive(Clone, Debug, Serialize)]
+pub struct ClickEvent {
+    pub link_id: i64,
+    pub alias: String,
+    pub clicked_at: String,
+    pub device_type: Option<&'static str>,
+    pub referrer: Option<String>,
+    /// Whether this is the first click from its IP address within the link's
+    /// deduplication window.
+    pub unique: bool,
+}
+
+/// Records a click on the link `link_id`/`alias` made by the client behind
+/// `headers`, whose hashed IP address is `ip_hash`. It is unique unless the
+/// same IP address clicked the link
+/// in the last `dedup_window_secs` seconds.
+pub fn record(
+    conn: &Connection,
+    link_id: i64,
+    alias: &str,
+    headers: &HeaderMap,
+    ip_hash: Option<String>,
+    dedup_window_secs: u64,
+) -> rusqlite::Result<ClickEvent> {
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    let click = Click {
+        link_id,
+        alias,
+        clicked_at: Utc::now(),
+        device_type: header(header::USER_AGENT).map(device_type),
+        referrer: header(header::REFERER),
+        ip_hash,
+    };
+    insert(conn, click, dedup_window_secs)
+}
+
+/// A click to store, however it was observed.
+struct Click<'a> {
+    link_id: i64,
+    alias: &'a str,
+    clicked_at: DateTime<Utc>,
+    device_type: Option<&'static str>,
+    referrer: Option<&'a str>,
+    ip_hash: Option<String>,
+}
+
+fn insert(conn: &Connection, click: Click, dedup_window_secs: u64) -> rusqlite::Result<ClickEvent> {
+    let Click {
+        link_id,
+        alias,
+        clicked_at,
+        device_type,
+        referrer,
+        ip_hash,
+    } = click;
+    // A window too long to subtract covers every earlier click.
+    let window_start = i64::try_from(dedup_window_secs)
+        .ok()
+        .and_then(TimeDelta::try_seconds)
+        .and_then(|window| clicked_at.checked_sub_signed(window))
+        .map(db::format_timestamp)
+        .unwrap_or_default();
+    let clicked_at = db::format_timestamp(clicked_at);
+    let unique = match &ip_hash {
+        Some(ip_hash) => !conn.query_row(
+            "SELECT EXISTS (
+                SELECT 1 FROM link_clicks
+                WHERE link_id = ?1 AND clicked_at > ?2 AND clicked_at <= ?3 AND ip_hash = ?4
+            )",
+            params![link_id, window_start, clicked_at, ip_hash],
+            |row| row.get::<_, bool>(0),
+        )?,
+        None => true,
+    };
+    // There is no IP geolocation source yet, so country_code is left null.
+    metrics::time_query(QueryType::InsertClick, || {
+        conn.execute(
+            "INSERT INTO link_clicks (link_id, clicked_at, device_type, referrer, ip_hash, is_unique)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![link_id, clicked_at, device_type, referrer, ip_hash, unique],
+        )
+    })?;
+    Ok(ClickEvent {
+        link_id,
+        alias: alias.to_string(),
+        clicked_at,
+        device_type,
+        referrer: referrer.map(str::to_string),
+        unique,
+    })
+}
+
+/// A click observed by a gateway that serves the redirect itself.
+#[derive(Deserialize)]
+pub struct IngestedClick {
+    alias: String,
+    clicked_at: Option<String>,
+    ip_hash: Option<String>,
+    referrer: Option<String>,
+    device_type: Option<String>,
+}
+
+/// Events sent to `POST /api/clicks`: one JSON object, or a JSON array.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum IngestedClicks {
+    One(IngestedClick),
+    Many(Vec<IngestedClick>),
+}
+
+#[derive(Serialize)]
+pub struct IngestReport {
+    ingested: usize,
+}
+
+/// Stores clicks reported by a gateway in front of ito, sent as JSON or as
+/// NDJSON with one event per line. They are stored in one transaction, so a
+/// bad event stores nothing.
+pub async fn ingest_clicks(
+    State(pool): State<ItoPool>,
+    State(config): State<Arc<Config>>,
+    State(clicks_tx): State<broadcast::Sender<ClickEvent>>,
+    headers: HeaderMap,
+    body: String,
+) -> Result<Json<IngestReport>, ItoJsonError> {
+    let is_ndjson = headers
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"application/x-ndjson"));
+    let invalid = |line: Option<usize>, err: serde_json::Error| {
+        let err = match line {
+            Some(line) => anyhow!("invalid click event on line {line}: {err}"),
+            None => anyhow!("invalid click events: {err}"),
+        };
+        ItoError {
+            err,
+            sc: StatusCode::BAD_REQUEST,
+        }
+    };
+    let events = if is_ndjson {
+        body.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| {
+                serde_json::from_str(line).map_err(|err| invalid(Some(index + 1), err))
+            })
+            .collect::<Result<Vec<IngestedClick>, _>>()?
+    } else {
+        match serde_json::from_str(&body).map_err(|err| invalid(None, err))? {
+            IngestedClicks::One(event) => vec![event],
+            IngestedClicks::Many(events) => events,
+        }
+    };
+    let bad_request = |err| ItoError {
+        err,
+        sc: StatusCode::BAD_REQUEST,
+    };
+    let mut clicks = Vec::with_capacity(events.len());
+    for event in events {
+        let clicked_at = match event.clicked_at.as_deref() {
+            Some(clicked_at) => crate::parse_optional_timestamp(clicked_at)?,
+            None => None,
+        };
+        let device_type = match event.device_type.as_deref() {
+            None => None,
+            Some("mobile") => Some("mobile"),
+            Some("tablet") => Some("tablet"),
+            Some("desktop") => Some("desktop"),
+            Some(other) => {
+                return Err(bad_request(anyhow!(
+                    "invalid device_type {other:?}, expected mobile, tablet or desktop"
+                ))
+                .into())
+            }
+        };
+        clicks.push((event, clicked_at.unwrap_or_else(Utc::now), device_type));
+    }
+
+    let recorded = db::interact(&pool, move |conn| -> Result<_, ItoError> {
+        let tx = conn.transaction()?;
+        let mut recorded = Vec::with_capacity(clicks.len());
+        for (event, clicked_at, device_type) in &clicks {
+            let link: Option<(i64, Option<u64>)> = tx
+                .query_row(
+                    "SELECT id, dedup_window_secs FROM links WHERE alias = ? COLLATE NOCASE",
+                    [&event.alias],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()?;
+            let Some((link_id, dedup_window_secs)) = link else {
+                return Err(bad_request(anyhow!(
+                    "no link has the alias {}",
+                    event.alias
+                )));
+            };
+            tx.execute(
+                "UPDATE links SET click_count = click_count + 1 WHERE id = ?",
+                [link_id],
+            )?;
+            let click = Click {
+                link_id,
+                alias: &event.alias,
+                clicked_at: *clicked_at,
+                device_type: *device_type,
+                referrer: event.referrer.as_deref(),
+                ip_hash: event.ip_hash.clone(),
+            };
+            let dedup_window_secs = dedup_window_secs.unwrap_or(config.default_dedup_window_secs);
+            recorded.push(insert(&tx, click, dedup_window_secs)?);
+        }
+        tx.commit()?;
+        Ok(recorded)
+    })
+    .await?;
+    let ingested = recorded.len();
+    for click in recorded {
+        // Sending only fails when nobody is subscribed.
+        let _ = clicks_tx.send(click);
+    }
+    Ok(Json(IngestReport { ingested }))
+}
+
+/// Streams every click recorded from now on to a WebSocket client, as JSON.
+pub async fn click_stream(
+    State(clicks_tx): State<broadcast::Sender<ClickEvent>>,
+    ws: WebSocketUpgrade,
+) -> impl IntoResponse {
+    ws.on_upgrade(move |socket| send_clicks(socket, clicks_tx.subscribe()))
+}
+
+async fn send_clicks(mut socket: WebSocket, mut clicks: broadcast::Receiver<ClickEvent>) {
+    let mut ping = tokio::time::interval(WS_PING_INTERVAL);
+    loop {
+        let message = tokio::select! {
+            click = clicks.recv() => match click {
+                Ok(click) => match serde_json::to_string(&click) {
+                    Ok(json) => Message::Text(json),
+                    Err(err) => {
+                        tracing::warn!("failed to serialize click event: {err}");
+                        continue;
+                    }
+                },
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    tracing::debug!("click stream subscriber missed {missed} clicks");
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            },
+            _ = ping.tick() => Message::Ping(Vec::new()),
+            // Drain what the client sends, which also notices it going away.
+            incoming = socket.recv() => match incoming {
+                Some(Ok(_)) => continue,
+                Some(Err(_)) | None => return,
+            },
+        };
+        if socket.send(message).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Buckets a `User-Agent` into `mobile`, `tablet` or `desktop`.
+pub fn device_type(user_agent: &str) -> &'static str {
+    let user_agent = user_agent.to_ascii_lowercase();
+    if user_agent.contains("ipad") || user_agent.contains("tablet") {
+        "tablet"
+    } else if user_agent.contains("mobi") || user_agent.contains("iphone") {
+        "mobile"
+    } else {
+        "desktop"
+    }
+}
+
+/// Whether a `User-Agent` contains any of the lowercase `bot_user_agents`.
+pub fn is_bot(user_agent: &str, bot_user_agents: &[String]) -> bool {
+    let user_agent = user_agent.to_ascii_lowercase();
+    bot_user_agents
+        .iter()
+        .any(|bot| user_agent.contains(bot.as_str()))
+}
+
+/// Clicks store a hash of the client IP so repeat visitors can be told apart
+/// without keeping the address itself. It is keyed with `secret`, since
+/// there are few enough addresses to reverse a plain hash by trying them all.
+pub fn hash_ip(secret: &str, addr: &SocketAddr) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(addr.ip().to_string().as_bytes());
+    format!("{:x}", mac.finalize().into_bytes())
+}
+
+#[derive(Deserialize)]
+pub struct ClickRange {
+    from: Option<String>,
+    to: Option<String>,
+}
+
+/// Streams the clicks on a link as CSV, optionally limited to `from <= clicked_at < to`.
+pub async fn export_csv(
+    State(ReadPool(pool)): State<ReadPool>,
+    State(config): State<Arc<Config>>,
+    Path(link_id): Path<i64>,
+    Query(range): Query<ClickRange>,
+) -> Result<impl IntoResponse, ItoError> {
+    let deadline = Instant::now() + Duration::from_secs(config.streaming_timeout_secs);
+    let from = parse_optional_timestamp(range.from.as_deref().unwrap_or_default())?
+        .map(db::format_timestamp);
+    let to = parse_optional_timestamp(range.to.as_deref().unwrap_or_default())?
+        .map(db::format_timestamp);
+
+    let exists: bool = db::interact(&pool, move |conn| {
+        conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM links WHERE id = ?)",
+            [link_id],
+            |row| row.get(0),
+        )
+        .map_err(ItoError::from)
+    })
+    .await?;
+    if !exists {
+        return Err(ItoError {
+            err: anyhow!("link {link_id} does not exist"),
+            sc: StatusCode::NOT_FOUND,
+        });
+    }
+
+    let conn = pool.get().await?;
+    let (tx, rx) = mpsc::channel(16);
+    tokio::spawn(async move {
+        conn.interact(move |conn| {
+            if let Err(err) = write_csv(conn, link_id, from, to, deadline, &tx) {
+                let _ = tx.blocking_send(Err(io::Error::other(err.to_string())));
+            }
+        })
+        .await
+    });
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"clicks-{link_id}.csv\""),
+            ),
+        ],
+        StreamBody::new(ReceiverStream::new(rx)),
+    ))
+}
+
+pub const EXPORT_CLICKS_SQL: &str =
+    "SELECT clicked_at, country_code, device_type, referrer, ip_hash FROM link_clicks
+    WHERE link_id = ?1
+        AND (?2 IS NULL OR clicked_at >= ?2)
+        AND (?3 IS NULL OR clicked_at < ?3)
+    ORDER BY clicked_at";
+
+fn write_csv(
+    conn: &Connection,
+    link_id: i64,
+    from: Option<String>,
+    to: Option<String>,
+    deadline: Instant,
+    tx: &mpsc::Sender<io::Result<Bytes>>,
+) -> Result<()> {
+    let mut statement = conn.prepare(EXPORT_CLICKS_SQL)?;
+    let mut rows = statement.query(params![link_id, from, to])?;
+
+    let mut csv = csv::Writer::from_writer(Vec::new());
+    csv.write_record([
+        "clicked_at",
+        "country_code",
+        "device_type",
+        "referrer",
+        "ip_hash",
+    ])?;
+    let mut buffered = 0;
+    while let Some(row) = rows.next()? {
+        if Instant::now() >= deadline {
+            bail!("export took longer than ITO_STREAMING_TIMEOUT_SECS");
+        }
+        let record: [Option<String>; 5] = [
+            row.get(0)?,
+            row.get(1)?,
+            row.get(2)?,
+            row.get(3)?,
+            row.get(4)?,
+        ];
+        csv.write_record(
+            record
+                .iter()
+                .map(|field| field.as_deref().unwrap_or_default()),
+        )?;
+        buffered += 1;
+        if buffered == CSV_ROWS_PER_CHUNK {
+            if !send_chunk(&mut csv, tx)? {
+                return Ok(());
+            }
+            buffered = 0;
+        }
+    }
+    send_chunk(&mut csv, tx)?;
+    Ok(())
+}
+
+/// Sends what has been written so far. Returns `false` once the client has gone away.
+fn send_chunk(
+    csv: &mut csv::Writer<Vec<u8>>,
+    tx: &mpsc::Sender<io::Result<Bytes>>,
+) -> Result<bool> {
+    let written = std::mem::replace(csv, csv::Writer::from_writer(Vec::new()));
+    let chunk = written.into_inner().map_err(|err| err.into_error())?;
+    Ok(tx.blocking_send(Ok(Bytes::from(chunk))).is_ok())
+}
+
+/// Rolls up clicks into `click_rollups` once a day.
+pub async fn roll_up_daily(pool: ItoPool) {
+    let mut interval = tokio::time::interval(ROLLUP_INTERVAL);
+    loop {
+        interval.tick().await;
+        let result = db::interact(&pool, |conn| anyhow::Ok(roll_up(conn)?)).await;
+        if let Err(err) = result {
+            tracing::warn!("failed to roll up clicks: {err:#}");
+        }
+    }
+}
+
+/// Counts clicks per link per day into `click_rollups`, for every complete day
+/// since the last rollup. Returns the number of rows written.
+pub fn roll_up(conn: &Connection) -> rusqlite::Result<usize> {
+    // The most recent rolled up day is redone in case it was rolled up early.
+    // Today may already have been rolled up on demand, so it doesn't count.
+    conn.execute(
+        "INSERT OR REPLACE INTO click_rollups (link_id, date, click_count)
+        SELECT link_id, date(clicked_at), COUNT(*) FROM link_clicks
+        WHERE clicked_at >= coalesce(
+            (SELECT max(date) FROM click_rollups WHERE date < date('now')), ''
+        )
+            AND clicked_at < date('now')
+        GROUP BY link_id, date(clicked_at)",
+        [],
+    )
+}
+
+#[derive(Deserialize)]
+pub struct RollUpParams {
+    date: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct RollUpReport {
+    date: String,
+    clicks_aggregated: i64,
+    links: Vec<LinkRollup>,
+}
+
+#[derive(Serialize)]
+pub struct LinkRollup {
+    link_id: i64,
+    click_count: i64,
+}
+
+/// Rolls up one day's clicks (today's by default) right away, replacing any
+/// earlier rollup of that day.
+pub async fn roll_up_now(
+    State(pool): State<ItoPool>,
+    Query(params): Query<RollUpParams>,
+) -> Result<Json<RollUpReport>, ItoJsonError> {
+    let date = match params.date {
+        Some(date) => NaiveDate::parse_from_str(&date, "%Y-%m-%d").map_err(|err| ItoError {
+            err: anyhow!("invalid date {date:?}, expected YYYY-MM-DD: {err}"),
+            sc: StatusCode::BAD_REQUEST,
+        })?,
+        None => Utc::now().date_naive(),
+    };
+    let date = format_date(date);
+    let report = db::interact(&pool, move |conn| -> Result<_, ItoError> {
+        let tx = conn.transaction()?;
+        // Deleted first, so links whose clicks have since gone don't keep a stale count.
+        tx.execute("DELETE FROM click_rollups WHERE date = ?", [&date])?;
+        tx.execute(
+            "INSERT OR REPLACE INTO click_rollups (link_id, date, click_count)
+            SELECT link_id, date(clicked_at), COUNT(*) FROM link_clicks
+            WHERE clicked_at >= ?1 AND clicked_at < date(?1, '+1 day')
+            GROUP BY link_id",
+            [&date],
+        )?;
+        let links = tx
+            .prepare(
+                "SELECT link_id, click_count FROM click_rollups WHERE date = ? ORDER BY link_id",
+            )?
+            .query_map([&date], |row| {
+                Ok(LinkRollup {
+                    link_id: row.get(0)?,
+                    click_count: row.get(1)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        tx.commit()?;
+        Ok(RollUpReport {
+            date,
+            clicks_aggregated: links.iter().map(|link| link.click_count).sum(),
+            links,
+        })
+    })
+    .await?;
+    Ok(Json(report))
+}
+
+pub const ANALYTICS_BY_DAY_SQL: &str = "SELECT date(clicked_at), COUNT(*) FROM link_clicks
+    WHERE link_id = ?1 AND clicked_at >= ?2
+    GROUP BY date(clicked_at)
+    ORDER BY date(clicked_at)";
+
+#[derive(Deserialize)]
+pub struct ClickStatsParams {
+    days: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct ClickStats {
+    link_id: i64,
+    days: u32,
+    total_clicks: i64,
+    unique_clicks: i64,
+    daily: Vec<DailyClicks>,
+}
+
+#[derive(Serialize)]
+pub struct DailyClicks {
+    date: String,
+    clicks: i64,
+}
+
+/// Clicks per day on a link over the last `days` days (30 by default), today included.
+pub async fn click_stats(
+    State(ReadPool(pool)): State<ReadPool>,
+    Path(link_id): Path<i64>,
+    Query(params): Query<ClickStatsParams>,
+) -> Result<Json<ClickStats>, ItoJsonError> {
+    let days = params.days.unwrap_or(30);
+    if days == 0 {
+        return Err(ItoError {
+            err: anyhow!("days must be at least 1"),
+            sc: StatusCode::BAD_REQUEST,
+        }
+        .into());
+    }
+    let today = Utc::now().date_naive();
+    let start = today
+        .checked_sub_days(Days::new(u64::from(days) - 1))
+        .ok_or_else(|| anyhow!("days is too large"))?;
+
+    let (daily, unique_clicks) = db::interact(&pool, move |conn| -> Result<_, ItoError> {
+        let exists: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM links WHERE id = ?)",
+            [link_id],
+            |row| row.get(0),
+        )?;
+        if !exists {
+            return Err(ItoError {
+                err: anyhow!("link {link_id} does not exist"),
+                sc: StatusCode::NOT_FOUND,
+            });
+        }
+
+        let mut daily = Vec::new();
+        let mut raw_start = start;
+        if days > MAX_RAW_STATS_DAYS {
+            // Today may have been rolled up on demand while it still gets clicks.
+            let rolled_through: Option<String> = conn.query_row(
+                "SELECT max(date) FROM click_rollups WHERE date < date('now')",
+                [],
+                |row| row.get(0),
+            )?;
+            if let Some(rolled_through) = rolled_through {
+                let mut statement = conn.prepare(
+                    "SELECT date, click_count FROM click_rollups
+                    WHERE link_id = ?1 AND date >= ?2 AND date <= ?3
+                    ORDER BY date",
+                )?;
+                let rows = statement.query_map(
+                    params![link_id, format_date(start), rolled_through],
+                    |row| {
+                        Ok(DailyClicks {
+                            date: row.get(0)?,
+                            clicks: row.get(1)?,
+                        })
+                    },
+                )?;
+                daily = rows.collect::<Result<_, _>>()?;
+                let next_day = NaiveDate::parse_from_str(&rolled_through, "%Y-%m-%d")?
+                    .checked_add_days(Days::new(1))
+                    .ok_or_else(|| anyhow!("invalid rollup date {rolled_through}"))?;
+                raw_start = raw_start.max(next_day);
+            }
+        }
+        // Whatever hasn't been rolled up yet, including today, comes from the raw clicks.
+        let mut statement = conn.prepare(ANALYTICS_BY_DAY_SQL)?;
+        let rows = statement.query_map(params![link_id, format_date(raw_start)], |row| {
+            Ok(DailyClicks {
+                date: row.get(0)?,
+                clicks: row.get(1)?,
+            })
+        })?;
+        for row in rows {
+            daily.push(row?);
+        }
+        // Rollups don't keep uniqueness, so this always counts the raw clicks.
+        let unique_clicks = conn.query_row(
+            "SELECT COUNT(*) FROM link_clicks
+            WHERE link_id = ?1 AND clicked_at >= ?2 AND is_unique",
+            params![link_id, format_date(start)],
+            |row| row.get(0),
+        )?;
+        Ok((daily, unique_clicks))
+    })
+    .await?;
+
+    Ok(Json(ClickStats {
+        link_id,
+        days,
+        total_clicks: daily.iter().map(|day| day.clicks).sum(),
+        unique_clicks,
+        daily,
+    }))
+}
+
+pub const HEATMAP_UTC_SQL: &str = "SELECT CAST(strftime('%w', clicked_at) AS INTEGER),
+        CAST(strftime('%H', clicked_at) AS INTEGER), COUNT(*)
+    FROM link_clicks WHERE link_id = ?
+    GROUP BY 1, 2";
+
+#[derive(Deserialize)]
+pub struct HeatmapParams {
+    tz: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct Heatmap {
+    /// Clicks by day of the week (0 is Sunday), then by hour of the day.
+    matrix: [[u64; 24]; 7],
+    timezone: String,
+}
+
+/// Clicks on a link by day of the week and hour of the day, in UTC or the IANA
+/// time zone `tz`.
+pub async fn click_heatmap(
+    State(ReadPool(pool)): State<ReadPool>,
+    Path(link_id): Path<i64>,
+    Query(params): Query<HeatmapParams>,
+) -> Result<Json<Heatmap>, ItoJsonError> {
+    let tz = match params.tz.as_deref() {
+        None | Some("UTC") => None,
+        Some(name) => Some(name.parse::<Tz>().map_err(|err| ItoError {
+            err: anyhow!("invalid time zone {name:?}: {err}"),
+            sc: StatusCode::BAD_REQUEST,
+        })?),
+    };
+    let matrix = db::interact(&pool, move |conn| -> Result<_, ItoError> {
+        let exists: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM links WHERE id = ?)",
+            [link_id],
+            |row| row.get(0),
+        )?;
+        if !exists {
+            return Err(ItoError {
+                err: anyhow!("link {link_id} does not exist"),
+                sc: StatusCode::NOT_FOUND,
+            });
+        }
+        let mut matrix = [[0; 24]; 7];
+        match tz {
+            // SQLite only knows UTC, so other zones are bucketed here instead.
+            Some(tz) => {
+                let mut statement =
+                    conn.prepare("SELECT clicked_at FROM link_clicks WHERE link_id = ?")?;
+                let mut rows = statement.query([link_id])?;
+                while let Some(row) = rows.next()? {
+                    let clicked_at =
+                        DateTime::parse_from_rfc3339(&row.get::<_, String>(0)?)?.with_timezone(&tz);
+                    let day = clicked_at.weekday().num_days_from_sunday() as usize;
+                    matrix[day][clicked_at.hour() as usize] += 1;
+                }
+            }
+            None => {
+                let mut statement = conn.prepare(HEATMAP_UTC_SQL)?;
+                let mut rows = statement.query([link_id])?;
+                while let Some(row) = rows.next()? {
+                    let (day, hour): (usize, usize) = (row.get(0)?, row.get(1)?);
+                    matrix[day][hour] = row.get(2)?;
+                }
+            }
+        }
+        Ok(matrix)
+    })
+    .await?;
+    Ok(Json(Heatmap {
+        matrix,
+        timezone: tz.map_or_else(|| "UTC".to_string(), |tz| tz.name().to_string()),
+    }))
+}
+
+fn format_date(date: NaiveDate) -> String {
+    date.format("%Y-%m-%d").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn click_stats_count_clicks_after_rolling_up_today() {
+        let pool = deadpool_sqlite::Config::new(":memory:")
+            .builder(deadpool_sqlite::Runtime::Tokio1)
+            .unwrap()
+            .max_size(1)
+            .post_create(db::init_hook(false))
+            .build()
+            .unwrap();
+        let click = |pool: ItoPool| async move {
+            db::interact(&pool, |conn| {
+                record(conn, 1, "docs", &HeaderMap::new(), None, 0)?;
+                anyhow::Ok(())
+            })
+            .await
+            .unwrap();
+        };
+        db::interact(&pool, |conn| {
+            db::migrate(conn)?;
+            conn.execute(
+                "INSERT INTO links (id, alias, target_url) VALUES (1, 'docs', 'https://example.com')",
+                [],
+            )?;
+            anyhow::Ok(())
+        })
+        .await
+        .unwrap();
+
+        click(pool.clone()).await;
+        let Json(report) = roll_up_now(State(pool.clone()), Query(RollUpParams { date: None }))
+            .await
+            .map_err(|err| err.0)
+            .unwrap();
+        assert_eq!(report.clicks_aggregated, 1);
+        click(pool.clone()).await;
+
+        let Json(stats) = click_stats(
+            State(ReadPool(pool)),
+            Path(1),
+            Query(ClickStatsParams {
+                days: Some(MAX_RAW_STATS_DAYS + 1),
+            }),
+        )
+        .await
+        .map_err(|err| err.0)
+        .unwrap();
+        assert_eq!(stats.total_clicks, 2);
+    }
+}
@@ -0,0 +1,81 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderMap},
+    response::{IntoResponse, Redirect},
+};
+use serde::Deserialize;
+use url::Url;
+
+use crate::config::Config;
+
+const COOKIE_NAME: &str = "cookie_consent";
+/// How long an answer is remembered before the banner asks again.
+const COOKIE_MAX_AGE_SECS: u64 = 365 * 24 * 60 * 60;
+
+/// Whether the browser has accepted optional cookies, or `None` if it hasn't
+/// been asked yet. Only the session and CSRF cookies, which ito can't work
+/// without, may be set before it has accepted.
+fn answer(headers: &HeaderMap) -> Option<bool> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .find_map(|cookie| cookie.trim().strip_prefix(COOKIE_NAME)?.strip_prefix('='))
+        .map(|answer| answer == "accepted")
+}
+
+/// Whether the root page should show the consent banner.
+pub fn needs_asking(headers: &HeaderMap) -> bool {
+    answer(headers).is_none()
+}
+
+#[derive(Deserialize)]
+pub struct ConsentParams {
+    accepted: bool,
+}
+
+/// Remembers the answer to the consent banner and goes back to the page it
+/// was shown on.
+pub async fn record(
+    State(config): State<Arc<Config>>,
+    Query(params): Query<ConsentParams>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let answer = if params.accepted {
+        "accepted"
+    } else {
+        "declined"
+    };
+    let secure = if config.base_url.scheme() == "https" {
+        "; Secure"
+    } else {
+        ""
+    };
+    let cookie = format!(
+        "{COOKIE_NAME}={answer}; Path=/; Max-Age={COOKIE_MAX_AGE_SECS}; SameSite=Lax{secure}"
+    );
+    (
+        [(header::SET_COOKIE, cookie)],
+        Redirect::to(&return_path(&config, &headers)),
+    )
+}
+
+/// The path of the `Referer`, when it is one of ito's own pages.
+fn return_path(config: &Config, headers: &HeaderMap) -> String {
+    headers
+        .get(header::REFERER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|referer| Url::parse(referer).ok())
+        // `//host` would be taken as another site's URL.
+        .filter(|referer| {
+            referer.origin() == config.base_url.origin() && !referer.path().starts_with("//")
+        })
+        .map(|referer| match referer.query() {
+            Some(query) => format!("{}?{query}", referer.path()),
+            None => referer.path().to_string(),
+        })
+        .unwrap_or_else(|| "/".to_string())
+}
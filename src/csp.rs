@@ -0,0 +1,60 @@
+use axum::http::{header, HeaderValue, Response};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sha2::{Digest, Sha256};
+use tower_http::set_header::{MakeHeaderValue, SetResponseHeaderLayer};
+
+/// Sets `Content-Security-Policy` on HTML responses.
+pub type CspLayer = SetResponseHeaderLayer<CspHeader>;
+
+/// The policy, for responses that are HTML pages.
+#[derive(Clone)]
+pub struct CspHeader(HeaderValue);
+
+impl<B> MakeHeaderValue<Response<B>> for CspHeader {
+    fn make_header_value(&mut self, response: &Response<B>) -> Option<HeaderValue> {
+        let is_html = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|content_type| content_type.starts_with("text/html"));
+        is_html.then(|| self.0.clone())
+    }
+}
+
+/// A layer enforcing `directives`. Handlers that set their own policy keep it.
+pub fn layer(directives: HeaderValue) -> CspLayer {
+    SetResponseHeaderLayer::if_not_present(header::CONTENT_SECURITY_POLICY, CspHeader(directives))
+}
+
+/// `directives` with `script` allowed to run inline, by its hash, in a
+/// sandbox that gives the page an opaque origin so the script can't read
+/// ito's cookies or call its endpoints as the visitor.
+pub fn sandboxing_inline_script(directives: &str, script: &str) -> String {
+    format!(
+        "{}; sandbox allow-scripts",
+        allowing_inline_script(directives, script)
+    )
+}
+
+/// `directives` with `script` allowed to run inline as well, by its hash.
+pub fn allowing_inline_script(directives: &str, script: &str) -> String {
+    let hash = format!("'sha256-{}'", STANDARD.encode(Sha256::digest(script)));
+    let mut found = false;
+    let mut directives: Vec<String> = directives
+        .split(';')
+        .map(str::trim)
+        .filter(|directive| !directive.is_empty())
+        .map(|directive| {
+            if directive.split_whitespace().next() == Some("script-src") {
+                found = true;
+                format!("{directive} {hash}")
+            } else {
+                directive.to_string()
+            }
+        })
+        .collect();
+    if !found {
+        directives.push(format!("script-src {hash}"));
+    }
+    directives.join("; ")
+}
@@ -0,0 +1,169 @@
+use std::{convert::Infallible, fmt, sync::Arc};
+
+use crate::{auth, config::Config, keys::SigningKeys, ItoError};
+use anyhow::anyhow;
+use axum::{
+    async_trait,
+    body::{Body, Bytes},
+    extract::{FromRef, FromRequest, FromRequestParts, State},
+    http::{header, request::Parts, HeaderMap, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, IntoResponseParts, Response, ResponseParts},
+};
+use http_body::Limited;
+
+const COOKIE_NAME: &str = "ito_csrf";
+const HEADER_NAME: &str = "x-csrf-token";
+/// The hidden form field templates put the token in.
+const FIELD_NAME: &str = "csrf_token";
+
+/// The CSRF token for the current browser session, for templates to embed in
+/// their forms. A new token is set as a cookie on the response it is part of.
+///
+/// Tokens are a random nonce signed with the current `SigningKeys`, so a cookie
+/// planted by another site (through a sibling subdomain, say) isn't accepted.
+pub struct CsrfToken {
+    token: String,
+    is_new: bool,
+    secure: bool,
+}
+
+impl fmt::Display for CsrfToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.token)
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for CsrfToken
+where
+    Arc<Config>: FromRef<S>,
+    Arc<SigningKeys>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let config = Arc::<Config>::from_ref(state);
+        let keys = Arc::<SigningKeys>::from_ref(state);
+        let secure = config.base_url.scheme() == "https";
+        if let Some(token) = cookie_token(&keys, &parts.headers) {
+            return Ok(Self {
+                token: token.to_string(),
+                is_new: false,
+                secure,
+            });
+        }
+        let mut nonce = [0; 16];
+        getrandom::fill(&mut nonce).map_err(|err| {
+            ItoError::from(anyhow!("failed to generate CSRF token: {err}")).into_response()
+        })?;
+        let nonce = hex(&nonce);
+        Ok(Self {
+            token: format!("{nonce}.{}", keys.sign(&nonce)),
+            is_new: true,
+            secure,
+        })
+    }
+}
+
+impl IntoResponseParts for CsrfToken {
+    type Error = Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        if self.is_new {
+            let secure = if self.secure { "; Secure" } else { "" };
+            let cookie = format!(
+                "{COOKIE_NAME}={}; Path=/; HttpOnly; SameSite=Strict{secure}",
+                self.token
+            );
+            if let Ok(cookie) = HeaderValue::try_from(cookie) {
+                res.headers_mut().append(header::SET_COOKIE, cookie);
+            }
+        }
+        Ok(res)
+    }
+}
+
+/// Rejects state-changing requests with 403 unless they carry the token from
+/// the CSRF cookie, in an `X-CSRF-Token` header or a `csrf_token` form field.
+/// Requests authenticated with a bearer token carry no ambient credentials
+/// and are let through.
+pub async fn verify_token(
+    State(config): State<Arc<Config>>,
+    State(keys): State<Arc<SigningKeys>>,
+    req: Request<Limited<Body>>,
+    next: Next<Limited<Body>>,
+) -> Result<Response, ItoError> {
+    let safe_method = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+    if !config.csrf_protection || safe_method || auth::bearer_token(req.headers()).is_some() {
+        return Ok(next.run(req).await);
+    }
+    let Some(expected) = cookie_token(&keys, req.headers()).map(str::to_string) else {
+        return Err(forbidden());
+    };
+
+    let header_token = req
+        .headers()
+        .get(HEADER_NAME)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let is_form = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|value| {
+            value
+                .as_bytes()
+                .starts_with(b"application/x-www-form-urlencoded")
+        });
+    let (req, submitted) = match header_token {
+        Some(token) => (req, Some(token)),
+        None if is_form => {
+            let (parts, body) = req.into_parts();
+            let body = Bytes::from_request(Request::new(body), &())
+                .await
+                .map_err(|err| ItoError {
+                    err: anyhow!("failed to read form: {err}"),
+                    sc: err.status(),
+                })?;
+            let token = url::form_urlencoded::parse(&body)
+                .find(|(name, _)| name == FIELD_NAME)
+                .map(|(_, token)| token.into_owned());
+            let len = body.len();
+            (
+                Request::from_parts(parts, Limited::new(Body::from(body), len)),
+                token,
+            )
+        }
+        None => (req, None),
+    };
+    match submitted {
+        Some(token) if auth::constant_time_eq(token.as_bytes(), expected.as_bytes()) => {
+            Ok(next.run(req).await)
+        }
+        _ => Err(forbidden()),
+    }
+}
+
+/// The token in the request's CSRF cookie, if it carries one signed with our keys.
+fn cookie_token<'a>(keys: &SigningKeys, headers: &'a HeaderMap) -> Option<&'a str> {
+    let token = headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .find_map(|cookie| cookie.trim().strip_prefix(COOKIE_NAME)?.strip_prefix('='))?;
+    let (nonce, signature) = token.split_once('.')?;
+    keys.verify(nonce, signature).then_some(token)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn forbidden() -> ItoError {
+    ItoError {
+        err: anyhow!("missing or invalid CSRF token"),
+        sc: StatusCode::FORBIDDEN,
+    }
+}
@@ -0,0 +1,313 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Utc};
+use deadpool_sqlite::{Hook, HookError, HookErrorCause, PoolError, Runtime};
+use rusqlite::{ffi, Connection};
+
+use crate::{config::Config, ItoPool, ReadPool};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS links (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        alias TEXT NOT NULL,
+        target_url TEXT NOT NULL
+    );
+    CREATE UNIQUE INDEX IF NOT EXISTS idx_links_alias ON links (alias);";
+
+/// The migration that makes aliases unique regardless of case.
+const CASE_INSENSITIVE_ALIAS_MIGRATION: usize = 3;
+
+// Applied in order on top of SCHEMA; `PRAGMA user_version` records how many
+// have run. Only ever append to this list.
+const MIGRATIONS: &[&str] = &[
+    "ALTER TABLE links ADD COLUMN created_at TEXT;
+    ALTER TABLE links ADD COLUMN click_count INTEGER NOT NULL DEFAULT 0;",
+    "CREATE TABLE users (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        username TEXT NOT NULL UNIQUE,
+        password_hash TEXT NOT NULL,
+        is_admin INTEGER NOT NULL DEFAULT 0,
+        created_at TEXT NOT NULL
+    );
+    ALTER TABLE links ADD COLUMN user_id INTEGER REFERENCES users (id);",
+    "CREATE TABLE audit_log (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        action TEXT NOT NULL,
+        actor_user_id INTEGER REFERENCES users (id),
+        link_id INTEGER,
+        details TEXT NOT NULL,
+        created_at TEXT NOT NULL
+    );",
+    "DROP INDEX idx_links_alias;
+    CREATE UNIQUE INDEX idx_links_alias ON links (alias COLLATE NOCASE);",
+    "ALTER TABLE links ADD COLUMN expires_at TEXT;
+    ALTER TABLE links ADD COLUMN notify_email TEXT;
+    ALTER TABLE links ADD COLUMN expiry_notified_at TEXT;",
+    "CREATE TABLE link_clicks (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        link_id INTEGER NOT NULL REFERENCES links (id) ON DELETE CASCADE,
+        clicked_at TEXT NOT NULL,
+        country_code TEXT,
+        device_type TEXT,
+        referrer TEXT,
+        ip_hash TEXT
+    );
+    CREATE INDEX idx_link_clicks_link_id_clicked_at ON link_clicks (link_id, clicked_at);",
+    "ALTER TABLE links ADD COLUMN cache_control TEXT;",
+    "ALTER TABLE links ADD COLUMN description TEXT;
+    CREATE TABLE tags (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        name TEXT NOT NULL UNIQUE COLLATE NOCASE
+    );
+    CREATE TABLE link_tags (
+        link_id INTEGER NOT NULL REFERENCES links (id) ON DELETE CASCADE,
+        tag_id INTEGER NOT NULL REFERENCES tags (id) ON DELETE CASCADE,
+        PRIMARY KEY (link_id, tag_id)
+    );",
+    "CREATE TABLE link_patterns (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        alias_pattern TEXT NOT NULL,
+        target_template TEXT NOT NULL,
+        priority INTEGER NOT NULL DEFAULT 0,
+        created_at TEXT NOT NULL
+    );",
+    "ALTER TABLE links ADD COLUMN last_checked_at TEXT;
+    ALTER TABLE links ADD COLUMN last_check_status INTEGER;
+    ALTER TABLE links ADD COLUMN last_check_error TEXT;",
+    "ALTER TABLE links ADD COLUMN max_clicks INTEGER;",
+    "CREATE INDEX idx_links_target_url ON links (target_url);",
+    "CREATE TABLE click_rollups (
+        link_id INTEGER NOT NULL REFERENCES links (id) ON DELETE CASCADE,
+        date TEXT NOT NULL,
+        click_count INTEGER NOT NULL,
+        PRIMARY KEY (link_id, date)
+    );",
+    "ALTER TABLE links ADD COLUMN redirect_delay_secs INTEGER;",
+    "ALTER TABLE links ADD COLUMN og_title TEXT;
+    ALTER TABLE links ADD COLUMN og_description TEXT;",
+    "ALTER TABLE tags ADD COLUMN parent_id INTEGER REFERENCES tags (id) ON DELETE SET NULL;",
+    "ALTER TABLE users ADD COLUMN totp_secret TEXT;
+    CREATE TABLE totp_recovery_codes (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+        code_hash TEXT NOT NULL,
+        used_at TEXT
+    );",
+    "ALTER TABLE links ADD COLUMN dedup_window_secs INTEGER;
+    ALTER TABLE link_clicks ADD COLUMN is_unique INTEGER NOT NULL DEFAULT 1;",
+    "CREATE TABLE link_templates (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        pattern TEXT NOT NULL,
+        target_template TEXT NOT NULL,
+        priority INTEGER NOT NULL DEFAULT 0
+    );",
+    "CREATE TABLE link_errors (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        alias TEXT NOT NULL,
+        message TEXT NOT NULL,
+        created_at TEXT NOT NULL
+    );",
+    "CREATE TABLE signing_keys (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        key TEXT NOT NULL,
+        created_at TEXT NOT NULL,
+        expires_at TEXT
+    );",
+    "CREATE TABLE idempotency_keys (
+        key TEXT NOT NULL,
+        user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+        response_body TEXT NOT NULL,
+        created_at TEXT NOT NULL,
+        PRIMARY KEY (key, user_id)
+    );",
+    "ALTER TABLE links ADD COLUMN response_content_type TEXT;",
+    "CREATE TABLE tag_invite_tokens (
+        token_hash TEXT PRIMARY KEY,
+        tag_id INTEGER NOT NULL REFERENCES tags (id) ON DELETE CASCADE,
+        expires_at TEXT NOT NULL,
+        view_count INTEGER NOT NULL DEFAULT 0,
+        max_views INTEGER,
+        created_at TEXT NOT NULL
+    );",
+    "ALTER TABLE links ADD COLUMN thumbnail_url TEXT;",
+    "ALTER TABLE links ADD COLUMN analytics_snippet TEXT;",
+    "ALTER TABLE users ADD COLUMN link_columns TEXT;",
+    "ALTER TABLE links ADD COLUMN access_password_hash TEXT;",
+    "ALTER TABLE links ADD COLUMN metadata_refreshed_at TEXT;",
+    "ALTER TABLE users ADD COLUMN totp_last_step INTEGER;",
+    "ALTER TABLE links ADD COLUMN thumbnail_png BLOB;",
+    "ALTER TABLE idempotency_keys ADD COLUMN alias_reused INTEGER NOT NULL DEFAULT FALSE;",
+];
+
+/// Builds the pool of the single connection to `config.db_path` that every
+/// write goes through, so writers queue here rather than on SQLite's lock.
+pub fn pool(config: &Config) -> Result<ItoPool> {
+    build_pool(config, 1, false)
+}
+
+/// Builds the pool of read-only connections to `config.db_path`, which WAL
+/// mode lets run alongside the writer.
+pub fn read_pool(config: &Config) -> Result<ReadPool> {
+    Ok(ReadPool(build_pool(config, config.read_pool_size, true)?))
+}
+
+fn build_pool(config: &Config, max_size: usize, read_only: bool) -> Result<ItoPool> {
+    let max_lifetime = Duration::from_secs(config.pool_max_lifetime_secs);
+    let idle_timeout = Duration::from_secs(config.pool_idle_timeout_secs);
+    let mut builder = deadpool_sqlite::Config::new(&config.db_path)
+        .builder(Runtime::Tokio1)?
+        .max_size(max_size)
+        .post_create(init_hook(config.verify_on_checkout));
+    if read_only {
+        builder = builder.post_create(Hook::async_fn(|conn, _| {
+            Box::pin(async move {
+                conn.interact(|conn| conn.pragma_update(None, "query_only", true))
+                    .await
+                    .map_err(|err| HookError::Abort(HookErrorCause::Message(err.to_string())))?
+                    .map_err(|err| HookError::Abort(HookErrorCause::Backend(err)))
+            })
+        }));
+    }
+    let pool = builder
+        // deadpool has no lifetime or idle limits of its own, so connections
+        // past either are dropped when they come back for reuse.
+        .pre_recycle(Hook::sync_fn(move |_, metrics| {
+            if metrics.age() > max_lifetime || metrics.last_used() > idle_timeout {
+                Err(HookError::Continue(None))
+            } else {
+                Ok(())
+            }
+        }))
+        .build()?;
+    Ok(pool)
+}
+
+/// Runs `init_connection` on every new pooled connection, and when `verify`
+/// is set, `check_integrity` too.
+pub fn init_hook(verify: bool) -> Hook {
+    Hook::async_fn(move |conn, _| {
+        Box::pin(async move {
+            conn.interact(move |conn| {
+                init_connection(conn)?;
+                if verify {
+                    check_integrity(conn)?;
+                }
+                Ok(())
+            })
+            .await
+            .map_err(|err| HookError::Abort(HookErrorCause::Message(err.to_string())))?
+            .map_err(|err| HookError::Abort(HookErrorCause::Backend(err)))
+        })
+    })
+}
+
+/// Runs `f` with a pooled connection on the blocking thread pool, so SQLite
+/// calls don't stall the async workers.
+pub async fn interact<T, E, F>(pool: &ItoPool, f: F) -> Result<T, E>
+where
+    F: FnOnce(&mut Connection) -> Result<T, E> + Send + 'static,
+    T: Send + 'static,
+    E: From<PoolError> + From<anyhow::Error> + Send + 'static,
+{
+    let conn = pool.get().await?;
+    let result = conn
+        .interact(f)
+        .await
+        .map_err(|err| anyhow!("database call failed: {err}"))?;
+    result
+}
+
+/// Runs `PRAGMA integrity_check`, so a database file that was replaced or
+/// damaged underneath the pool is noticed early.
+fn check_integrity(conn: &Connection) -> rusqlite::Result<()> {
+    let result: String = conn.query_row("PRAGMA integrity_check", [], |row| row.get(0))?;
+    if result == "ok" {
+        Ok(())
+    } else {
+        Err(rusqlite::Error::SqliteFailure(
+            ffi::Error::new(ffi::SQLITE_CORRUPT),
+            Some(format!("integrity check failed: {result}")),
+        ))
+    }
+}
+
+/// SQLite foreign key enforcement is per connection.
+fn init_connection(conn: &mut Connection) -> rusqlite::Result<()> {
+    // WAL lets readers carry on while a write is in progress.
+    conn.execute_batch("PRAGMA foreign_keys = ON; PRAGMA journal_mode = WAL;")
+}
+
+pub fn migrate(conn: &mut Connection) -> Result<()> {
+    let tx = conn.transaction()?;
+    tx.execute_batch(SCHEMA)?;
+    let version: usize = tx.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        if index == CASE_INSENSITIVE_ALIAS_MIGRATION {
+            check_alias_case_conflicts(&tx)?;
+        }
+        tx.execute_batch(migration)?;
+    }
+    if version < MIGRATIONS.len() {
+        tx.pragma_update(None, "user_version", MIGRATIONS.len())?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Fails, naming them, if some aliases differ only in case, since the
+/// case-insensitive unique index can't be created until they are renamed.
+/// SQLite's `lower` folds ASCII only, as `COLLATE NOCASE` does.
+fn check_alias_case_conflicts(conn: &Connection) -> Result<()> {
+    let conflicts = conn
+        .prepare(
+            "SELECT group_concat(alias, ', ') FROM links
+            GROUP BY lower(alias) HAVING count(*) > 1 ORDER BY lower(alias)",
+        )?
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    if !conflicts.is_empty() {
+        bail!(
+            "aliases are about to become case-insensitive, but these differ only in case; \
+            rename all but one of each before starting ito again: {}",
+            conflicts.join("; ")
+        );
+    }
+    Ok(())
+}
+
+/// Formats a timestamp the way it is stored in the database, which matches
+/// `strftime('%Y-%m-%dT%H:%M:%SZ', 'now')` so the two compare as strings.
+pub fn format_timestamp(timestamp: DateTime<Utc>) -> String {
+    timestamp.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrate_lists_aliases_that_differ_only_in_case() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(SCHEMA).unwrap();
+        for migration in &MIGRATIONS[..CASE_INSENSITIVE_ALIAS_MIGRATION] {
+            conn.execute_batch(migration).unwrap();
+        }
+        conn.pragma_update(None, "user_version", CASE_INSENSITIVE_ALIAS_MIGRATION)
+            .unwrap();
+        conn.execute_batch(
+            "INSERT INTO links (alias, target_url) VALUES
+                ('Docs', 'https://example.com/1'),
+                ('docs', 'https://example.com/2'),
+                ('wiki', 'https://example.com/3');",
+        )
+        .unwrap();
+
+        let err = migrate(&mut conn).unwrap_err().to_string();
+        assert!(err.ends_with(": Docs, docs"), "{err}");
+
+        conn.execute("UPDATE links SET alias = 'docs-2' WHERE alias = 'docs'", [])
+            .unwrap();
+        migrate(&mut conn).unwrap();
+    }
+}
@@ -0,0 +1,189 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{anyhow, Result};
+use axum::{
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+use chrono::{DateTime, Days, TimeDelta, Utc};
+use icalendar::{Calendar, Component, Event, EventLike};
+use rusqlite::{params_from_iter, ToSql};
+use serde::Deserialize;
+
+use crate::{
+    api::{LinkFilter, LINK_FILTER_CONDITION, LINK_FILTER_TAGS_SQL},
+    config::Config,
+    db,
+    mail::Mailer,
+    ItoError, ItoPool, ReadPool,
+};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Periodically emails the `notify_email` of each link expiring within the
+/// next `warning_hours` hours. Each link is only ever warned about once.
+pub async fn notify_upcoming_expirations(pool: ItoPool, mailer: Arc<Mailer>, warning_hours: u64) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(err) = send_expiry_warnings(&pool, &mailer, warning_hours).await {
+            tracing::warn!("failed to send expiry warnings: {err:#}");
+        }
+    }
+}
+
+struct ExpiringLink {
+    id: i64,
+    alias: String,
+    target_url: String,
+    expires_at: String,
+    notify_email: String,
+}
+
+async fn send_expiry_warnings(pool: &ItoPool, mailer: &Mailer, warning_hours: u64) -> Result<()> {
+    let now = Utc::now();
+    let horizon = i64::try_from(warning_hours)
+        .ok()
+        .and_then(TimeDelta::try_hours)
+        .and_then(|warning| now.checked_add_signed(warning))
+        .ok_or_else(|| anyhow!("expiry warning hours {warning_hours} is too large"))?;
+    let links = db::interact(pool, move |conn| {
+        let mut statement = conn.prepare(
+            "SELECT id, alias, target_url, expires_at, notify_email FROM links
+            WHERE notify_email IS NOT NULL AND expiry_notified_at IS NULL
+                AND expires_at > ?1 AND expires_at <= ?2",
+        )?;
+        let rows = statement.query_map(
+            [db::format_timestamp(now), db::format_timestamp(horizon)],
+            |row| {
+                Ok(ExpiringLink {
+                    id: row.get(0)?,
+                    alias: row.get(1)?,
+                    target_url: row.get(2)?,
+                    expires_at: row.get(3)?,
+                    notify_email: row.get(4)?,
+                })
+            },
+        )?;
+        anyhow::Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    })
+    .await?;
+
+    let mut notified = Vec::with_capacity(links.len());
+    for link in links {
+        let to = match link.notify_email.parse() {
+            Ok(to) => to,
+            Err(err) => {
+                tracing::warn!("skipping expiry warning for {}: {err}", link.alias);
+                continue;
+            }
+        };
+        let subject = format!("Short link '{}' expires soon", link.alias);
+        let body = format!(
+            "The short link '{}' to {} expires at {}.",
+            link.alias, link.target_url, link.expires_at
+        );
+        match mailer.send(to, &subject, body).await {
+            Ok(()) => notified.push(link.id),
+            Err(err) => tracing::warn!("failed to send expiry warning for {}: {err:#}", link.alias),
+        }
+    }
+
+    if !notified.is_empty() {
+        let placeholders = vec!["?"; notified.len()].join(", ");
+        db::interact(pool, move |conn| {
+            conn.execute(
+                &format!(
+                    "UPDATE links SET expiry_notified_at = strftime('%Y-%m-%dT%H:%M:%SZ', 'now')
+                    WHERE id IN ({placeholders})"
+                ),
+                params_from_iter(notified),
+            )?;
+            anyhow::Ok(())
+        })
+        .await?;
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct ExpiringParams {
+    days: Option<u32>,
+    #[serde(flatten)]
+    filter: LinkFilter,
+}
+
+/// An iCalendar feed with an event for each link expiring within the next
+/// `days` days, `Config::expiry_calendar_days` by default, that pass the
+/// `LinkFilter`.
+pub async fn expiring_links_calendar(
+    State(ReadPool(pool)): State<ReadPool>,
+    State(config): State<Arc<Config>>,
+    Query(params): Query<ExpiringParams>,
+) -> Result<impl IntoResponse, ItoError> {
+    let now = Utc::now();
+    let days = params.days.unwrap_or(config.expiry_calendar_days);
+    let horizon = now
+        .checked_add_days(Days::new(days.into()))
+        .ok_or_else(|| ItoError {
+            err: anyhow!("days is too large"),
+            sc: StatusCode::BAD_REQUEST,
+        })?;
+    let filename = params.filter.filename("expiring", "ics");
+    let links = db::interact(&pool, move |conn| {
+        let mut statement = conn.prepare(&format!(
+            "{LINK_FILTER_TAGS_SQL}
+            SELECT id, alias, target_url, expires_at FROM links
+            WHERE expires_at > :now AND expires_at <= :horizon AND {LINK_FILTER_CONDITION}
+            ORDER BY expires_at"
+        ))?;
+        let (now, horizon) = (db::format_timestamp(now), db::format_timestamp(horizon));
+        let filter_params = params.filter.sql_params();
+        let mut sql_params: Vec<(&str, &dyn ToSql)> = vec![(":now", &now), (":horizon", &horizon)];
+        sql_params.extend(
+            filter_params
+                .iter()
+                .map(|(name, value)| (*name, value as &dyn ToSql)),
+        );
+        let rows = statement.query_map(sql_params.as_slice(), |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?;
+        anyhow::Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    })
+    .await?;
+
+    // Stable UIDs let calendar apps update events rather than duplicate them.
+    let host = config.base_url.host_str().unwrap_or("ito");
+    let mut calendar = Calendar::new();
+    calendar.name("Expiring short links");
+    for (id, alias, target_url, expires_at) in links {
+        let expires_at = DateTime::parse_from_rfc3339(&expires_at)?.with_timezone(&Utc);
+        calendar.push(
+            Event::new()
+                .uid(&format!("link-{id}-expiry@{host}"))
+                .summary(&format!("Short link '{alias}' expires"))
+                .starts(expires_at)
+                .description(&target_url)
+                .done(),
+        );
+    }
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                "text/calendar; charset=utf-8".to_string(),
+            ),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            ),
+        ],
+        calendar.done().to_string(),
+    ))
+}
@@ -0,0 +1,52 @@
+use askama::{Html, MarkupDisplay};
+use chrono::{DateTime, TimeDelta, Utc};
+use url::Url;
+
+/// Timestamps older than this are shown as a date instead.
+const MAX_RELATIVE_AGE: TimeDelta = TimeDelta::days(30);
+
+/// The attributes for a link to a user-supplied URL: it opens in a new
+/// tab, with no `window.opener` and no referrer for the destination.
+/// Use as `<a {{url|safe_href|safe}}>`.
+pub fn safe_href(url: &Url) -> askama::Result<String> {
+    let href = MarkupDisplay::new_unsafe(url.as_str(), Html);
+    Ok(format!(
+        "href=\"{href}\" rel=\"noopener noreferrer\" target=\"_blank\""
+    ))
+}
+
+/// How long ago `dt` was, like "3 minutes ago" or "yesterday", or its date
+/// once it is more than 30 days old.
+pub fn relative_time(dt: &DateTime<Utc>) -> askama::Result<String> {
+    Ok(relative_to(*dt, Utc::now()))
+}
+
+fn relative_to(dt: DateTime<Utc>, now: DateTime<Utc>) -> String {
+    let age = now - dt;
+    if age > MAX_RELATIVE_AGE {
+        return dt.format("%Y-%m-%d").to_string();
+    }
+    if (TimeDelta::days(1)..TimeDelta::days(2)).contains(&age) {
+        return "yesterday".to_string();
+    }
+    // Clocks that have drifted a little put some timestamps in the future.
+    timeago::Formatter::new().convert(age.to_std().unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_to_falls_back_to_the_date() {
+        let now = DateTime::parse_from_rfc3339("2024-03-31T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let ago = |delta| relative_to(now - delta, now);
+        assert_eq!(ago(TimeDelta::minutes(3)), "3 minutes ago");
+        assert_eq!(ago(TimeDelta::hours(30)), "yesterday");
+        assert_eq!(ago(TimeDelta::weeks(2)), "2 weeks ago");
+        assert_eq!(ago(TimeDelta::days(45)), "2024-02-15");
+        assert_eq!(ago(TimeDelta::seconds(-5)), "now");
+    }
+}
@@ -0,0 +1,116 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use axum::{
+    body::{self, Body, Bytes, Full},
+    extract::{FromRequest, State},
+    http::{HeaderMap, Request, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use http_body::Limited;
+use sha2::{Digest, Sha256};
+use tokio::sync::OnceCell;
+use tower_sessions::Session;
+
+use crate::{config::Config, users, ItoError};
+
+/// Recent form submissions by hash, so one submitted twice in quick
+/// succession is answered from the first's response. Unlike idempotency keys
+/// this needs nothing from the client, which suits plain HTML forms.
+#[derive(Default)]
+pub struct FormDedup {
+    submissions: Mutex<HashMap<[u8; 32], (Instant, Slot)>>,
+}
+
+/// Filled in with the response once the first submission has one.
+type Slot = Arc<OnceCell<CachedResponse>>;
+
+struct CachedResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl CachedResponse {
+    fn to_response(&self) -> Response {
+        let mut response = Response::new(body::boxed(Full::from(self.body.clone())));
+        *response.status_mut() = self.status;
+        *response.headers_mut() = self.headers.clone();
+        response
+    }
+}
+
+impl FormDedup {
+    /// The slot for the submission hashed as `hash`, shared with any other
+    /// submission of it in the last `window`.
+    fn slot(&self, hash: [u8; 32], window: Duration) -> Slot {
+        let now = Instant::now();
+        let mut submissions = self
+            .submissions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        submissions.retain(|_, (submitted, _)| now.duration_since(*submitted) < window);
+        submissions
+            .entry(hash)
+            .or_insert_with(|| (now, Arc::default()))
+            .1
+            .clone()
+    }
+}
+
+/// Runs a signed-in user's form submission once, even if it arrives again
+/// within `Config::form_dedup_window_ms`, as a double-clicked submit button
+/// makes it. A repeat waits for the first to finish and gets its response.
+pub async fn dedup_forms(
+    State(config): State<Arc<Config>>,
+    State(dedup): State<Arc<FormDedup>>,
+    req: Request<Limited<Body>>,
+    next: Next<Limited<Body>>,
+) -> Result<Response, ItoError> {
+    let user_id = req
+        .extensions()
+        .get::<Session>()
+        .and_then(users::session_user_id);
+    let (Some(user_id), false) = (user_id, config.form_dedup_window_ms == 0) else {
+        return Ok(next.run(req).await);
+    };
+    let (parts, body) = req.into_parts();
+    let body = Bytes::from_request(Request::new(body), &())
+        .await
+        .map_err(|err| ItoError {
+            err: anyhow!("failed to read form: {err}"),
+            sc: err.status(),
+        })?;
+    // Only the same user's identical submission counts as a repeat.
+    let hash = Sha256::new()
+        .chain_update(user_id.to_be_bytes())
+        .chain_update(parts.method.as_str())
+        .chain_update(parts.uri.to_string())
+        .chain_update([0])
+        .chain_update(&body)
+        .finalize()
+        .into();
+    let slot = dedup.slot(hash, Duration::from_millis(config.form_dedup_window_ms));
+    let len = body.len();
+    let req = Request::from_parts(parts, Limited::new(Body::from(body), len));
+    // If the first submission is cancelled, the next one runs instead.
+    let cached = slot
+        .get_or_try_init(|| async {
+            let (parts, body) = next.run(req).await.into_parts();
+            let body = Bytes::from_request(Request::new(body), &())
+                .await
+                .map_err(|err| anyhow!("failed to read response: {err}"))?;
+            Ok::<_, ItoError>(CachedResponse {
+                status: parts.status,
+                headers: parts.headers,
+                body,
+            })
+        })
+        .await?;
+    Ok(cached.to_response())
+}
@@ -0,0 +1,104 @@
+use std::time::Duration;
+
+use anyhow::anyhow;
+use axum::{
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::{db, ItoError, ItoPool};
+
+static IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+const MAX_KEY_LENGTH: usize = 255;
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// The request's `Idempotency-Key`, if it sent one.
+pub fn key(headers: &HeaderMap) -> Result<Option<String>, ItoError> {
+    let Some(value) = headers.get(&IDEMPOTENCY_KEY) else {
+        return Ok(None);
+    };
+    match value.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LENGTH => Ok(Some(key.to_string())),
+        _ => Err(ItoError {
+            err: anyhow!("Idempotency-Key must be 1 to {MAX_KEY_LENGTH} visible ASCII characters"),
+            sc: StatusCode::BAD_REQUEST,
+        }),
+    }
+}
+
+/// The outcome of a request made with an `Idempotency-Key`, which a retry
+/// of it gets again.
+pub struct Outcome {
+    pub response_body: String,
+    /// Whether the created link's alias belonged to a recently deleted link.
+    pub alias_reused: bool,
+}
+
+impl IntoResponse for Outcome {
+    fn into_response(self) -> Response {
+        let mut response = (
+            [(header::CONTENT_TYPE, "application/json")],
+            self.response_body,
+        )
+            .into_response();
+        if self.alias_reused {
+            response
+                .headers_mut()
+                .insert("x-ito-alias-reused", HeaderValue::from_static("true"));
+        }
+        response
+    }
+}
+
+/// The outcome stored for `key` by `user_id` in the last 24 hours.
+pub fn lookup(conn: &Connection, key: &str, user_id: i64) -> rusqlite::Result<Option<Outcome>> {
+    conn.query_row(
+        "SELECT response_body, alias_reused FROM idempotency_keys
+        WHERE key = ?1 AND user_id = ?2
+            AND created_at > strftime('%Y-%m-%dT%H:%M:%SZ', 'now', '-1 day')",
+        params![key, user_id],
+        |row| {
+            Ok(Outcome {
+                response_body: row.get(0)?,
+                alias_reused: row.get(1)?,
+            })
+        },
+    )
+    .optional()
+}
+
+/// Remembers `outcome` as the outcome of `key`, replacing an expired entry.
+pub fn store(
+    conn: &Connection,
+    key: &str,
+    user_id: i64,
+    outcome: &Outcome,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO idempotency_keys
+            (key, user_id, response_body, alias_reused, created_at)
+        VALUES (?1, ?2, ?3, ?4, strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))",
+        params![key, user_id, outcome.response_body, outcome.alias_reused],
+    )?;
+    Ok(())
+}
+
+/// Deletes keys older than 24 hours every hour.
+pub async fn prune_periodically(pool: ItoPool) {
+    let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let result = db::interact(&pool, |conn| {
+            anyhow::Ok(conn.execute(
+                "DELETE FROM idempotency_keys
+                WHERE created_at <= strftime('%Y-%m-%dT%H:%M:%SZ', 'now', '-1 day')",
+                [],
+            )?)
+        })
+        .await;
+        if let Err(err) = result {
+            tracing::warn!("failed to prune idempotency keys: {err:#}");
+        }
+    }
+}
@@ -0,0 +1,279 @@
+use anyhow::anyhow;
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use std::sync::Arc;
+
+use crate::{
+    alias, check_target_domain, config::Config, db, handle_sqlite_err, redirect_loop_error,
+    redirects_back_to, ItoError, ItoJsonError, ItoPool,
+};
+
+/// What to do with an imported row whose alias already exists.
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    /// Keep the existing link and import the rest.
+    #[default]
+    Skip,
+    /// Point the existing link at the imported target.
+    Overwrite,
+    /// Import nothing if any alias already exists.
+    Error,
+}
+
+/// What else, besides the alias, makes an imported row a duplicate.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DedupBy {
+    /// Skip rows whose target some link already points at.
+    TargetUrl,
+}
+
+#[derive(Deserialize)]
+pub struct ImportParams {
+    #[serde(default)]
+    conflict_policy: ConflictPolicy,
+    dedup_by: Option<DedupBy>,
+}
+
+#[derive(Deserialize)]
+pub struct ImportRow {
+    alias: String,
+    target_url: Url,
+    #[serde(default)]
+    description: Option<String>,
+}
+
+/// The aliases affected by an import, by what happened to them.
+#[derive(Default, Serialize)]
+pub struct ImportReport {
+    created: Vec<String>,
+    overwritten: Vec<String>,
+    skipped: Vec<String>,
+}
+
+/// Imports a JSON array of `{alias, target_url, description?}` objects.
+pub async fn import_json(
+    State(pool): State<ItoPool>,
+    State(config): State<Arc<Config>>,
+    Query(params): Query<ImportParams>,
+    Json(rows): Json<Vec<ImportRow>>,
+) -> Result<Json<ImportReport>, ItoJsonError> {
+    let report = db::interact(&pool, move |conn| import_rows(conn, &config, rows, params)).await?;
+    Ok(Json(report))
+}
+
+/// Imports CSV with an `alias,target_url,description` header row.
+pub async fn import_csv(
+    State(pool): State<ItoPool>,
+    State(config): State<Arc<Config>>,
+    Query(params): Query<ImportParams>,
+    body: String,
+) -> Result<Json<ImportReport>, ItoJsonError> {
+    let rows = csv::Reader::from_reader(body.as_bytes())
+        .deserialize()
+        .collect::<Result<Vec<ImportRow>, _>>()
+        .map_err(|err| ItoError {
+            err: anyhow!("invalid CSV: {err}"),
+            sc: StatusCode::BAD_REQUEST,
+        })?;
+    let report = db::interact(&pool, move |conn| import_rows(conn, &config, rows, params)).await?;
+    Ok(Json(report))
+}
+
+/// Imports `rows` in a single transaction, so a failed import changes nothing.
+/// Rows are validated as if each link were created on its own.
+fn import_rows(
+    conn: &mut Connection,
+    config: &Config,
+    rows: Vec<ImportRow>,
+    params: ImportParams,
+) -> Result<ImportReport, ItoError> {
+    let tx = conn.transaction()?;
+    let mut report = ImportReport::default();
+    let mut conflicts = Vec::new();
+    for mut row in rows {
+        let bad_row = |err| ItoError {
+            err: anyhow!("row {:?}: {err}", row.alias),
+            sc: StatusCode::BAD_REQUEST,
+        };
+        if row.alias.is_empty() {
+            return Err(bad_row(anyhow!("imported links need an alias")));
+        }
+        row.alias = alias::normalize(&row.alias).map_err(bad_row)?;
+        check_target_domain(config, &row.target_url)?;
+        if redirects_back_to(&tx, config, &row.alias, &row.target_url)? {
+            return Err(redirect_loop_error(&row.alias));
+        }
+        if let Some(DedupBy::TargetUrl) = params.dedup_by {
+            let existing_alias: Option<String> = tx
+                .query_row(
+                    "SELECT alias FROM links WHERE target_url = ? ORDER BY id LIMIT 1",
+                    [&row.target_url],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            if let Some(existing_alias) = existing_alias {
+                report.skipped.push(existing_alias);
+                continue;
+            }
+        }
+        let exists = tx
+            .query_row(
+                "SELECT 1 FROM links WHERE alias = ? COLLATE NOCASE",
+                [&row.alias],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some();
+        if exists {
+            match params.conflict_policy {
+                ConflictPolicy::Skip => {
+                    report.skipped.push(row.alias);
+                    continue;
+                }
+                ConflictPolicy::Error => {
+                    conflicts.push(row.alias);
+                    continue;
+                }
+                ConflictPolicy::Overwrite => {}
+            }
+        }
+        // An upsert rather than INSERT OR REPLACE, so an overwritten link keeps
+        // its id, owner and click history.
+        tx.execute(
+            "INSERT INTO links (alias, target_url, description, created_at)
+            VALUES (?1, ?2, ?3, strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))
+            ON CONFLICT (alias COLLATE NOCASE) DO UPDATE SET
+                target_url = excluded.target_url,
+                description = coalesce(excluded.description, description)",
+            params![row.alias, row.target_url, row.description],
+        )
+        .map_err(handle_sqlite_err)?;
+        if exists {
+            report.overwritten.push(row.alias);
+        } else {
+            report.created.push(row.alias);
+        }
+    }
+    if !conflicts.is_empty() {
+        return Err(ItoError {
+            err: anyhow!(
+                "import aborted, these aliases already exist: {}",
+                conflicts.join(", ")
+            ),
+            sc: StatusCode::CONFLICT,
+        });
+    }
+    tx.commit()?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup() -> (Connection, Config) {
+        let mut conn = Connection::open_in_memory().unwrap();
+        db::migrate(&mut conn).unwrap();
+        conn.execute(
+            "INSERT INTO links (alias, target_url, created_at)
+            VALUES ('docs', 'https://example.com/old', '2024-01-01T00:00:00Z')",
+            [],
+        )
+        .unwrap();
+        (conn, Config::from_env().unwrap())
+    }
+
+    fn rows() -> Vec<ImportRow> {
+        [
+            ("Docs", "https://example.com/new"),
+            ("wiki", "https://example.com/wiki"),
+        ]
+        .into_iter()
+        .map(|(alias, target_url)| ImportRow {
+            alias: alias.to_string(),
+            target_url: target_url.parse().unwrap(),
+            description: None,
+        })
+        .collect()
+    }
+
+    fn params(conflict_policy: ConflictPolicy) -> ImportParams {
+        ImportParams {
+            conflict_policy,
+            dedup_by: None,
+        }
+    }
+
+    fn target_of(conn: &Connection, alias: &str) -> Option<String> {
+        conn.query_row(
+            "SELECT target_url FROM links WHERE alias = ? COLLATE NOCASE",
+            [alias],
+            |row| row.get(0),
+        )
+        .optional()
+        .unwrap()
+    }
+
+    #[test]
+    fn skip_keeps_existing_links() {
+        let (mut conn, config) = setup();
+        let report = import_rows(&mut conn, &config, rows(), params(ConflictPolicy::Skip)).unwrap();
+        assert_eq!(report.skipped, ["Docs"]);
+        assert_eq!(report.created, ["wiki"]);
+        assert_eq!(target_of(&conn, "docs").unwrap(), "https://example.com/old");
+    }
+
+    #[test]
+    fn overwrite_repoints_existing_links() {
+        let (mut conn, config) = setup();
+        let report = import_rows(
+            &mut conn,
+            &config,
+            rows(),
+            params(ConflictPolicy::Overwrite),
+        )
+        .unwrap();
+        assert_eq!(report.overwritten, ["Docs"]);
+        assert_eq!(report.created, ["wiki"]);
+        assert_eq!(target_of(&conn, "docs").unwrap(), "https://example.com/new");
+    }
+
+    #[test]
+    fn error_imports_nothing() {
+        let (mut conn, config) = setup();
+        let err = import_rows(&mut conn, &config, rows(), params(ConflictPolicy::Error))
+            .err()
+            .unwrap();
+        assert_eq!(err.sc, StatusCode::CONFLICT);
+        assert!(target_of(&conn, "wiki").is_none());
+    }
+
+    #[test]
+    fn rows_are_validated_like_new_links() {
+        let (mut conn, mut config) = setup();
+        config.blocked_target_domains = vec!["blocked.example.com".to_string()];
+        let mut bad_rows = rows();
+        bad_rows[1].alias = "a b".to_string();
+        let err = import_rows(&mut conn, &config, bad_rows, params(ConflictPolicy::Skip))
+            .err()
+            .unwrap();
+        assert_eq!(err.sc, StatusCode::BAD_REQUEST);
+
+        let mut bad_rows = rows();
+        bad_rows[1].target_url = "https://blocked.example.com/".parse().unwrap();
+        let err = import_rows(&mut conn, &config, bad_rows, params(ConflictPolicy::Skip))
+            .err()
+            .unwrap();
+        assert_eq!(err.sc, StatusCode::BAD_REQUEST);
+        assert!(target_of(&conn, "wiki").is_none());
+    }
+}
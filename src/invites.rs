@@ -0,0 +1,216 @@
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use askama::Template;
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{TimeDelta, Utc};
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use crate::{
+    audit, config::Config, db, keys, render_markdown, HtmlTemplate, ItoError, ItoJsonError, ItoPool,
+};
+
+const DEFAULT_INVITE_LIFETIME: &str = "7d";
+
+#[derive(Deserialize)]
+pub struct CreateInviteInput {
+    /// How long the invite works for, like `24h` or `30d`.
+    expires_in: Option<String>,
+    max_views: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct Invite {
+    url: Url,
+    expires_at: String,
+    max_views: Option<u32>,
+}
+
+/// Makes a URL anyone can use to see the links tagged `tag_id`, or with one
+/// of its descendant tags, until it expires or has been viewed `max_views`
+/// times. Only a hash of the token is kept, so the URL can't be shown again.
+pub async fn create_invite(
+    State(pool): State<ItoPool>,
+    State(config): State<Arc<Config>>,
+    Path(tag_id): Path<i64>,
+    Json(input): Json<CreateInviteInput>,
+) -> Result<(StatusCode, Json<Invite>), ItoJsonError> {
+    let lifetime = keys::parse_duration(
+        input
+            .expires_in
+            .as_deref()
+            .unwrap_or(DEFAULT_INVITE_LIFETIME),
+    )
+    .map_err(|err| ItoError {
+        err,
+        sc: StatusCode::BAD_REQUEST,
+    })?;
+    let expires_at = TimeDelta::from_std(lifetime)
+        .ok()
+        .and_then(|lifetime| Utc::now().checked_add_signed(lifetime))
+        .map(db::format_timestamp)
+        .ok_or_else(|| ItoError {
+            err: anyhow!("invite lifetime is too long"),
+            sc: StatusCode::BAD_REQUEST,
+        })?;
+    let mut token = [0; 32];
+    getrandom::fill(&mut token).map_err(|err| anyhow!("failed to generate invite: {err}"))?;
+    let token: String = token.iter().map(|byte| format!("{byte:02x}")).collect();
+    let token_hash = hash_token(&token);
+    let max_views = input.max_views;
+    let invite_expires_at = expires_at.clone();
+    db::interact(&pool, move |conn| -> Result<_, ItoError> {
+        let tx = conn.transaction()?;
+        let tag: String = tx
+            .query_row("SELECT name FROM tags WHERE id = ?", [tag_id], |row| {
+                row.get(0)
+            })
+            .optional()?
+            .ok_or_else(|| ItoError {
+                err: anyhow!("no tag with id {tag_id}"),
+                sc: StatusCode::NOT_FOUND,
+            })?;
+        tx.execute(
+            "INSERT INTO tag_invite_tokens (token_hash, tag_id, expires_at, max_views, created_at)
+            VALUES (?1, ?2, ?3, ?4, strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))",
+            params![token_hash, tag_id, invite_expires_at, max_views],
+        )?;
+        audit::record(
+            &tx,
+            "create_tag_invite",
+            None,
+            None,
+            json!({ "tag": tag, "expires_at": invite_expires_at, "max_views": max_views }),
+        )?;
+        tx.commit()?;
+        Ok(())
+    })
+    .await?;
+    let mut url = config.base_url.clone();
+    url.path_segments_mut()
+        .map_err(|_| anyhow!("ITO_BASE_URL cannot be a base"))?
+        .pop_if_empty()
+        .extend(["g", &token]);
+    Ok((
+        StatusCode::CREATED,
+        Json(Invite {
+            url,
+            expires_at,
+            max_views,
+        }),
+    ))
+}
+
+fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token))
+}
+
+#[derive(Serialize)]
+struct InvitedLink {
+    alias: String,
+    short_url: Url,
+    target_url: Url,
+    description: Option<String>,
+    #[serde(skip)]
+    description_html: Option<String>,
+}
+
+#[derive(Serialize, Template)]
+#[template(path = "invite.html")]
+struct InviteTemplate {
+    tag: String,
+    links: Vec<InvitedLink>,
+}
+
+/// The links an invite shares, as JSON when the client accepts it and as a
+/// read-only page otherwise. Every view counts towards the invite's
+/// `max_views`.
+pub async fn view_invite(
+    State(pool): State<ItoPool>,
+    State(config): State<Arc<Config>>,
+    Path(token): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, ItoError> {
+    let token_hash = hash_token(&token);
+    let (tag, links) = db::interact(&pool, move |conn| -> Result<_, ItoError> {
+        let tx = conn.transaction()?;
+        let viewed = tx.execute(
+            "UPDATE tag_invite_tokens SET view_count = view_count + 1
+            WHERE token_hash = ?
+                AND expires_at > strftime('%Y-%m-%dT%H:%M:%SZ', 'now')
+                AND (max_views IS NULL OR view_count < max_views)",
+            [&token_hash],
+        )?;
+        if viewed == 0 {
+            return Err(ItoError {
+                err: anyhow!("this invite doesn't exist or is no longer valid"),
+                sc: StatusCode::NOT_FOUND,
+            });
+        }
+        let (tag_id, tag): (i64, String) = tx.query_row(
+            "SELECT tags.id, tags.name FROM tag_invite_tokens
+            JOIN tags ON tags.id = tag_invite_tokens.tag_id
+            WHERE token_hash = ?",
+            [&token_hash],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let links = tx
+            .prepare(
+                "WITH RECURSIVE invite_tags (id) AS (
+                    SELECT ?1
+                    UNION
+                    SELECT tags.id FROM tags JOIN invite_tags ON tags.parent_id = invite_tags.id
+                )
+                SELECT alias, target_url, description FROM links
+                WHERE (expires_at IS NULL OR expires_at > strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))
+                    -- Listing a PIN-protected link's target would get around its PIN.
+                    AND access_password_hash IS NULL
+                    AND EXISTS (
+                        SELECT 1 FROM link_tags
+                        WHERE link_tags.link_id = links.id
+                            AND link_tags.tag_id IN (SELECT id FROM invite_tags)
+                    )
+                ORDER BY alias",
+            )?
+            .query_map([tag_id], |row| {
+                Ok((row.get::<_, String>(0)?, row.get(1)?, row.get(2)?))
+            })?
+            .collect::<rusqlite::Result<Vec<(String, Url, Option<String>)>>>()?;
+        tx.commit()?;
+        Ok((tag, links))
+    })
+    .await?;
+    let links = links
+        .into_iter()
+        .map(|(alias, target_url, description)| {
+            Ok(InvitedLink {
+                short_url: config.short_url(&alias)?,
+                alias,
+                target_url,
+                description_html: description.as_deref().map(render_markdown),
+                description,
+            })
+        })
+        .collect::<Result<Vec<_>, ItoError>>()?;
+    let page = InviteTemplate { tag, links };
+    let wants_json = headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/json"));
+    // Each view is counted, so none may be served from a cache.
+    let cache_control = [(header::CACHE_CONTROL, "private, no-store")];
+    Ok(if wants_json {
+        (cache_control, Json(page)).into_response()
+    } else {
+        (cache_control, HtmlTemplate(page)).into_response()
+    })
+}
@@ -0,0 +1,259 @@
+use std::{
+    sync::{Arc, PoisonError, RwLock},
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{TimeDelta, Utc};
+use hmac::{Hmac, Mac};
+use rusqlite::Connection;
+use serde_json::json;
+use sha2::Sha256;
+
+use crate::{audit, config::Config, db, ItoPool};
+
+/// How often the server picks up keys rotated by `ito key rotate`.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A new random 32 byte signing key, base64 encoded.
+pub fn generate() -> Result<String> {
+    let mut key = [0; 32];
+    getrandom::fill(&mut key).map_err(|err| anyhow!("failed to generate key: {err}"))?;
+    Ok(STANDARD.encode(key))
+}
+
+/// Parses a duration like `90s`, `30m`, `24h` or `7d`.
+pub fn parse_duration(input: &str) -> Result<Duration> {
+    let split = input.len() - input.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let (value, unit) = input.split_at(split);
+    let value: u64 = value
+        .parse()
+        .with_context(|| format!("invalid duration {input:?}"))?;
+    let unit_secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => bail!("invalid duration {input:?}, expected a number followed by s, m, h or d"),
+    };
+    let secs = value
+        .checked_mul(unit_secs)
+        .ok_or_else(|| anyhow!("duration {input:?} is too long"))?;
+    Ok(Duration::from_secs(secs))
+}
+
+/// Adds a new signing key. Keys that were current stay valid for verification
+/// for `grace_period`, and keys whose grace period has ended are deleted.
+///
+/// The first rotation also stores an empty key, which stands for
+/// `ITO_CSRF_SECRET`, so tokens signed with it keep working for the grace
+/// period too.
+pub fn rotate(conn: &mut Connection, grace_period: Duration) -> Result<()> {
+    let expires_at = TimeDelta::from_std(grace_period)
+        .ok()
+        .and_then(|grace_period| Utc::now().checked_add_signed(grace_period))
+        .ok_or_else(|| anyhow!("grace period is too long"))?;
+    let expires_at = db::format_timestamp(expires_at);
+    let tx = conn.transaction()?;
+    delete_expired(&tx)?;
+    let mut retired = tx.execute(
+        "UPDATE signing_keys SET expires_at = ? WHERE expires_at IS NULL",
+        [&expires_at],
+    )?;
+    let stored: i64 = tx.query_row("SELECT count(*) FROM signing_keys", [], |row| row.get(0))?;
+    if stored == 0 {
+        tx.execute(
+            "INSERT INTO signing_keys (key, created_at, expires_at)
+            VALUES ('', strftime('%Y-%m-%dT%H:%M:%SZ', 'now'), ?)",
+            [&expires_at],
+        )?;
+        retired += 1;
+    }
+    tx.execute(
+        "INSERT INTO signing_keys (key, created_at)
+        VALUES (?, strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))",
+        [generate()?],
+    )?;
+    let key_id = tx.last_insert_rowid();
+    audit::record(
+        &tx,
+        "rotate_signing_key",
+        None,
+        None,
+        json!({ "key_id": key_id, "retired_keys": retired, "old_keys_expire_at": expires_at }),
+    )?;
+    tx.commit()?;
+    Ok(())
+}
+
+fn delete_expired(conn: &Connection) -> rusqlite::Result<usize> {
+    conn.execute(
+        "DELETE FROM signing_keys WHERE expires_at <= strftime('%Y-%m-%dT%H:%M:%SZ', 'now')",
+        [],
+    )
+}
+
+/// The keys CSRF tokens are signed with: the newest stored key, or
+/// `ITO_CSRF_SECRET` until a key has been rotated in. Keys in their grace
+/// period, `ITO_CSRF_SECRET` included, still verify.
+pub struct SigningKeys {
+    /// Newest first.
+    keys: RwLock<Vec<Vec<u8>>>,
+    fallback: Vec<u8>,
+}
+
+impl SigningKeys {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            keys: RwLock::default(),
+            fallback: config.csrf_secret.as_bytes().to_vec(),
+        }
+    }
+
+    /// Replaces the keys with those stored now, deleting ones that expired.
+    pub fn refresh(&self, conn: &Connection) -> Result<()> {
+        delete_expired(conn)?;
+        let keys = conn
+            .prepare("SELECT key FROM signing_keys ORDER BY id DESC")?
+            .query_map([], |row| row.get::<_, String>(0))?
+            .map(|key| match key?.as_str() {
+                "" => Ok(self.fallback.clone()),
+                key => Ok(STANDARD.decode(key)?),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        *self.keys.write().unwrap_or_else(PoisonError::into_inner) = keys;
+        Ok(())
+    }
+
+    /// `message` signed with the current key, as hex.
+    pub fn sign(&self, message: &str) -> String {
+        let keys = self.keys.read().unwrap_or_else(PoisonError::into_inner);
+        hmac_hex(keys.first().unwrap_or(&self.fallback), message)
+    }
+
+    /// Whether `signature` is `message` signed with any valid key.
+    pub fn verify(&self, message: &str, signature: &str) -> bool {
+        let keys = self.keys.read().unwrap_or_else(PoisonError::into_inner);
+        if keys.is_empty() {
+            return constant_time_eq(&hmac_hex(&self.fallback, message), signature);
+        }
+        keys.iter()
+            .any(|key| constant_time_eq(&hmac_hex(key, message), signature))
+    }
+}
+
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    crate::auth::constant_time_eq(a.as_bytes(), b.as_bytes())
+}
+
+fn hmac_hex(key: &[u8], message: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(message.as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Keeps `keys` in step with the database, so rotations take effect without
+/// a restart.
+pub async fn refresh_periodically(pool: ItoPool, keys: Arc<SigningKeys>) {
+    let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+    loop {
+        interval.tick().await;
+        let keys = keys.clone();
+        let result = db::interact(&pool, move |conn| keys.refresh(conn)).await;
+        if let Err(err) = result {
+            tracing::warn!("failed to refresh signing keys: {err:#}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn migrated() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        db::migrate(&mut conn).unwrap();
+        conn
+    }
+
+    #[test]
+    fn hmac_matches_rfc_4231() {
+        assert_eq!(
+            hmac_hex(b"Jefe", "what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn signs_with_fallback_until_a_key_is_stored() {
+        let keys = SigningKeys {
+            keys: RwLock::default(),
+            fallback: b"secret".to_vec(),
+        };
+        let signature = keys.sign("nonce");
+        assert!(keys.verify("nonce", &signature));
+        assert!(!keys.verify("other", &signature));
+        assert!(!keys.verify("nonce", &hmac_hex(b"not the secret", "nonce")));
+    }
+
+    #[test]
+    fn rotated_out_keys_verify_until_their_grace_period_ends() {
+        let mut conn = migrated();
+        let keys = SigningKeys {
+            keys: RwLock::default(),
+            fallback: b"secret".to_vec(),
+        };
+        let fallback_signature = keys.sign("nonce");
+
+        rotate(&mut conn, Duration::from_secs(3600)).unwrap();
+        keys.refresh(&conn).unwrap();
+        let first_signature = keys.sign("nonce");
+        assert_ne!(first_signature, fallback_signature);
+        assert!(keys.verify("nonce", &fallback_signature));
+
+        rotate(&mut conn, Duration::from_secs(3600)).unwrap();
+        keys.refresh(&conn).unwrap();
+        let second_signature = keys.sign("nonce");
+        assert_ne!(second_signature, first_signature);
+        assert!(keys.verify("nonce", &first_signature));
+
+        rotate(&mut conn, Duration::ZERO).unwrap();
+        keys.refresh(&conn).unwrap();
+        assert!(!keys.verify("nonce", &second_signature));
+        assert!(keys.verify("nonce", &first_signature));
+        assert!(keys.verify("nonce", &fallback_signature));
+    }
+
+    #[test]
+    fn fallback_stops_verifying_when_the_first_grace_period_ends() {
+        let mut conn = migrated();
+        let keys = SigningKeys {
+            keys: RwLock::default(),
+            fallback: b"secret".to_vec(),
+        };
+        let fallback_signature = keys.sign("nonce");
+
+        rotate(&mut conn, Duration::ZERO).unwrap();
+        keys.refresh(&conn).unwrap();
+        assert!(!keys.verify("nonce", &fallback_signature));
+        assert!(keys.verify("nonce", &keys.sign("nonce")));
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("30m").unwrap(), Duration::from_secs(30 * 60));
+        assert_eq!(
+            parse_duration("7d").unwrap(),
+            Duration::from_secs(7 * 24 * 60 * 60)
+        );
+        assert!(parse_duration("7w").is_err());
+        assert!(parse_duration("h").is_err());
+        assert!(parse_duration(&format!("{}d", u64::MAX / 2)).is_err());
+    }
+}
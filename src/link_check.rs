@@ -0,0 +1,180 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{anyhow, Result};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use rusqlite::params;
+use serde::Serialize;
+use tokio::{sync::Semaphore, task::JoinSet};
+
+use crate::{db, handle_sqlite_err, users::User, ItoError, ItoJsonError, ItoPool};
+
+/// What the last check of a link found, for the badge on the root page.
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Reachability {
+    Reachable,
+    Unreachable,
+    Unchecked,
+}
+
+impl Reachability {
+    /// From a link's `last_checked_at` being set and its `last_check_status`,
+    /// which is NULL when the target didn't respond at all.
+    pub fn from_check(checked: bool, status: Option<u16>) -> Self {
+        match (checked, status) {
+            (false, _) => Reachability::Unchecked,
+            (true, Some(status)) if status < 400 => Reachability::Reachable,
+            (true, _) => Reachability::Unreachable,
+        }
+    }
+
+    pub fn color(self) -> &'static str {
+        match self {
+            Reachability::Reachable => "green",
+            Reachability::Unreachable => "red",
+            Reachability::Unchecked => "gray",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Reachability::Reachable => "Reachable",
+            Reachability::Unreachable => "Unreachable",
+            Reachability::Unchecked => "Not checked yet",
+        }
+    }
+}
+
+/// Periodically sends a `HEAD` request to every link's target and records
+/// the outcome, with at most `concurrency` requests in flight at once.
+pub async fn check_links_periodically(
+    pool: ItoPool,
+    client: reqwest::Client,
+    interval: Duration,
+    concurrency: usize,
+) {
+    let semaphore = Arc::new(Semaphore::new(concurrency));
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        if let Err(err) = check_links(&pool, &client, &semaphore).await {
+            tracing::warn!("failed to check links: {err:#}");
+        }
+    }
+}
+
+async fn check_links(
+    pool: &ItoPool,
+    client: &reqwest::Client,
+    semaphore: &Arc<Semaphore>,
+) -> Result<()> {
+    let links = db::interact(pool, |conn| {
+        let mut statement = conn.prepare("SELECT id, target_url FROM links")?;
+        let rows = statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        anyhow::Ok(rows.collect::<Result<Vec<(i64, String)>, _>>()?)
+    })
+    .await?;
+
+    let mut checks = JoinSet::new();
+    for (link_id, target_url) in links {
+        let permit = semaphore.clone().acquire_owned().await?;
+        let pool = pool.clone();
+        let client = client.clone();
+        checks.spawn(async move {
+            let (status, error) = head(&client, link_id, &target_url).await;
+            drop(permit);
+            record(&pool, link_id, status, error).await
+        });
+    }
+    while let Some(result) = checks.join_next().await {
+        if let Err(err) = result? {
+            tracing::warn!("failed to record link check: {err:#}");
+        }
+    }
+    Ok(())
+}
+
+/// Sends a `HEAD` request to `target_url`, giving the status it got or why
+/// it didn't get one.
+async fn head(
+    client: &reqwest::Client,
+    link_id: i64,
+    target_url: &str,
+) -> (Option<u16>, Option<String>) {
+    match client.head(target_url).send().await {
+        Ok(response) => {
+            let status = response.status();
+            tracing::debug!(link_id, %target_url, %status, "checked link");
+            (Some(status.as_u16()), None)
+        }
+        Err(err) => {
+            tracing::debug!(link_id, %target_url, error = %err, "link check failed");
+            (None, Some(err.to_string()))
+        }
+    }
+}
+
+async fn record(
+    pool: &ItoPool,
+    link_id: i64,
+    status: Option<u16>,
+    error: Option<String>,
+) -> Result<()> {
+    db::interact(pool, move |conn| {
+        conn.execute(
+            "UPDATE links SET last_checked_at = strftime('%Y-%m-%dT%H:%M:%SZ', 'now'),
+                last_check_status = ?1, last_check_error = ?2
+            WHERE id = ?3",
+            params![status, error, link_id],
+        )?;
+        anyhow::Ok(())
+    })
+    .await
+}
+
+#[derive(Serialize)]
+pub struct RecheckResult {
+    reachability: Reachability,
+    label: &'static str,
+    color: &'static str,
+    last_check_status: Option<u16>,
+}
+
+/// Checks one link right away, rather than waiting for the next round.
+pub async fn recheck(
+    State(pool): State<ItoPool>,
+    State(client): State<reqwest::Client>,
+    user: User,
+    Path(link_id): Path<i64>,
+) -> Result<Json<RecheckResult>, ItoJsonError> {
+    let target_url = db::interact(&pool, move |conn| {
+        let (target_url, owner_id): (String, Option<i64>) = conn
+            .query_row(
+                "SELECT target_url, user_id FROM links WHERE id = ?",
+                [link_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .map_err(handle_sqlite_err)?;
+        if !user.can_modify(owner_id) {
+            return Err(ItoError {
+                err: anyhow!("link {link_id} belongs to another user"),
+                sc: StatusCode::FORBIDDEN,
+            });
+        }
+        Ok(target_url)
+    })
+    .await?;
+    let (status, error) = head(&client, link_id, &target_url).await;
+    record(&pool, link_id, status, error).await?;
+    let reachability = Reachability::from_check(true, status);
+    Ok(Json(RecheckResult {
+        reachability,
+        label: reachability.label(),
+        color: reachability.color(),
+        last_check_status: status,
+    }))
+}
@@ -0,0 +1,34 @@
+use anyhow::Result;
+use lettre::{message::Mailbox, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+
+use crate::config::Config;
+
+/// Sends plain-text notification emails over SMTP.
+pub struct Mailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl Mailer {
+    /// Returns `None` when SMTP is not configured.
+    pub fn from_config(config: &Config) -> Result<Option<Self>> {
+        let (Some(smtp_url), Some(from)) = (&config.smtp_url, &config.smtp_from) else {
+            return Ok(None);
+        };
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::from_url(smtp_url)?.build();
+        Ok(Some(Self {
+            transport,
+            from: from.clone(),
+        }))
+    }
+
+    pub async fn send(&self, to: Mailbox, subject: &str, body: String) -> Result<()> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(subject)
+            .body(body)?;
+        self.transport.send(message).await?;
+        Ok(())
+    }
+}
@@ -1,49 +1,486 @@
-use std::net::SocketAddr;
+use std::{
+    io::{self, BufWriter, Write},
+    net::SocketAddr,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use anyhow::Result;
+use access_log::{RedirectEvent, SyslogSink};
+use anyhow::{anyhow, bail, Context, Result};
 use askama::Template;
 use axum::{
-    extract::{Form, Path, State},
-    http::StatusCode,
+    body::{Bytes, StreamBody},
+    error_handling::HandleErrorLayer,
+    extract::DefaultBodyLimit,
+    extract::{ConnectInfo, Form, FromRef, Path, Query, State},
+    http::{header, header::HeaderName, HeaderMap, HeaderValue, StatusCode, Uri},
+    middleware,
     response::{Html, IntoResponse, Redirect, Response},
-    routing::{delete, get, post},
-    Router, Server,
+    routing::{delete, get, post, put},
+    BoxError, Json, Router, Server,
 };
-use r2d2::Pool;
-use r2d2_sqlite::SqliteConnectionManager;
-use rusqlite::params;
-use serde::Deserialize;
+use axum_server::tls_rustls::RustlsConfig;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use clap::{Parser, Subcommand};
+use clicks::ClickEvent;
+use config::{Config, LogFormat};
+use csrf::CsrfToken;
+use lettre::Address;
+use link_check::Reachability;
+use metrics::QueryType;
+use rate_limit::{PinAttempts, RateLimiter, RedirectLimiter};
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, TransactionBehavior};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio_stream::wrappers::ReceiverStream;
+use tower::ServiceBuilder;
+use tower_http::{limit::RequestBodyLimitLayer, timeout::TimeoutLayer};
+use tower_sessions::{MemoryStore, SessionManagerLayer};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+use unicode_normalization::UnicodeNormalization;
 use url::Url;
+use users::User;
+
+mod access_log;
+mod admin;
+mod alias;
+mod analytics;
+mod api;
+mod audit;
+mod auth;
+mod cleanup;
+mod clicks;
+mod config;
+mod consent;
+mod csp;
+mod csrf;
+mod db;
+mod expiry;
+mod filters;
+mod form_dedup;
+mod idempotency;
+mod import;
+mod invites;
+mod keys;
+mod link_check;
+mod mail;
+mod metadata;
+mod metrics;
+mod pagination;
+mod patterns;
+mod pin;
+mod preferences;
+mod qr;
+mod rate_limit;
+mod readme;
+mod ssrf;
+mod tags;
+mod telemetry;
+mod thumbnails;
+mod tls;
+mod totp;
+mod users;
+
+/// How many click events a slow `/ws/clicks` subscriber may fall behind by.
+const CLICK_EVENTS_CAPACITY: usize = 1024;
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Serve HTTPS using a freshly generated self-signed certificate
+    #[arg(long)]
+    dev_tls: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create a user account, reading its password from ITO_PASSWORD or stdin
+    AddUser {
+        username: String,
+        /// Allow the user to see and manage every link
+        #[arg(long)]
+        admin: bool,
+    },
+    /// Manage the key CSRF tokens are signed with
+    Key {
+        #[command(subcommand)]
+        command: KeyCommand,
+    },
+    /// Write a Markdown directory of every unexpired link, by tag
+    ExportReadme {
+        #[arg(long, default_value = "README.md")]
+        output: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum KeyCommand {
+    /// Print a new random key, base64 encoded
+    Generate,
+    /// Start signing with a new key, still accepting the old one for a while
+    Rotate {
+        /// How long the old key stays valid, like 30m, 24h or 7d
+        #[arg(long, default_value = "24h", value_parser = keys::parse_duration)]
+        grace_period: Duration,
+    },
+}
+
+fn init_tracing(log_format: LogFormat, otel_endpoint: Option<&Url>) -> Result<()> {
+    let fmt = tracing_subscriber::fmt::layer();
+    let fmt = match log_format {
+        LogFormat::Full => fmt.boxed(),
+        LogFormat::Pretty => fmt.pretty().boxed(),
+        LogFormat::Compact => fmt.compact().boxed(),
+        LogFormat::Json => fmt.json().boxed(),
+    };
+    let otel = otel_endpoint.map(telemetry::layer).transpose()?;
+    tracing_subscriber::registry()
+        .with(fmt)
+        .with(otel)
+        .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .init();
+    Ok(())
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // todo path to db from config
-    let manager = SqliteConnectionManager::file("./data/ito.db");
-    let pool = r2d2::Pool::new(manager)?;
-    pool.get()?.execute_batch(
-        "BEGIN;
-        CREATE TABLE IF NOT EXISTS links (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            alias TEXT NOT NULL,
-            target_url TEXT NOT NULL
-        );
-        CREATE UNIQUE INDEX IF NOT EXISTS idx_links_alias ON links (alias);
-        COMMIT;",
+    let config = Config::from_env();
+    // Problems with the configuration are logged in the default format.
+    init_tracing(
+        config
+            .as_ref()
+            .map_or(LogFormat::default(), |config| config.log_format),
+        config
+            .as_ref()
+            .ok()
+            .and_then(|config| config.otel_endpoint.as_ref()),
     )?;
+    let cli = Cli::parse();
+    let config = match config {
+        Ok(config) => config,
+        Err(errors) => {
+            for err in &errors {
+                tracing::error!("{err}");
+            }
+            bail!("invalid configuration ({} errors)", errors.len());
+        }
+    };
+
+    if let Some(dir) = config.db_path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let pool = db::pool(&config)?;
+    db::interact(&pool, db::migrate).await?;
+    let read_pool = db::read_pool(&config)?;
+
+    if let Some(command) = cli.command {
+        match command {
+            Command::AddUser { username, admin } => {
+                db::interact(&pool, move |conn| users::add_user(conn, &username, admin)).await?
+            }
+            Command::Key {
+                command: KeyCommand::Generate,
+            } => println!("{}", keys::generate()?),
+            Command::Key {
+                command: KeyCommand::Rotate { grace_period },
+            } => db::interact(&pool, move |conn| keys::rotate(conn, grace_period)).await?,
+            Command::ExportReadme { output } => {
+                let readme = db::interact(&pool, move |conn| readme::render(conn, &config)).await?;
+                std::fs::write(&output, readme)
+                    .with_context(|| format!("failed to write {}", output.display()))?;
+            }
+        }
+        return Ok(());
+    }
+
+    if cli.dev_tls && config.tls_cert_path.is_some() {
+        bail!("--dev-tls cannot be combined with ITO_TLS_CERT_PATH");
+    }
+    let tls_paths = if cli.dev_tls {
+        Some(tls::generate_dev_certificate()?)
+    } else {
+        config
+            .tls_cert_path
+            .clone()
+            .zip(config.tls_key_path.clone())
+    };
+    if config.http_port.is_some() && tls_paths.is_none() {
+        bail!("ITO_HTTP_PORT requires TLS to be configured");
+    }
+
+    let session_layer = ServiceBuilder::new()
+        .layer(HandleErrorLayer::new(|_: BoxError| async {
+            StatusCode::BAD_REQUEST
+        }))
+        .layer(
+            SessionManagerLayer::new(MemoryStore::default())
+                .with_secure(config.base_url.scheme() == "https"),
+        );
+    if let Some(mailer) = mail::Mailer::from_config(&config)? {
+        tokio::spawn(expiry::notify_upcoming_expirations(
+            pool.clone(),
+            Arc::new(mailer),
+            config.expiry_warning_hours,
+        ));
+    }
+    let link_check_client =
+        config.http_client(Duration::from_secs(config.link_check_timeout_secs))?;
+    let metadata_client =
+        ssrf::PublicClient::new(&config, Duration::from_secs(config.link_check_timeout_secs))?;
+    metrics::start_buffer(config.metrics_buffer_size);
+    tokio::spawn(clicks::roll_up_daily(pool.clone()));
+    tokio::spawn(idempotency::prune_periodically(pool.clone()));
+    if let Some(after_days) = config.cleanup_unclicked_after_days {
+        tokio::spawn(cleanup::delete_unclicked_periodically(
+            pool.clone(),
+            after_days,
+        ));
+    }
+    tokio::spawn(link_check::check_links_periodically(
+        pool.clone(),
+        link_check_client.clone(),
+        Duration::from_secs(config.link_check_interval_secs),
+        config.link_check_concurrency,
+    ));
+
+    // Access logging is best effort, so a syslog socket that can't be opened
+    // doesn't stop ito from serving redirects.
+    let syslog = config
+        .syslog_host
+        .as_deref()
+        .and_then(|host| match SyslogSink::connect(host) {
+            Ok(sink) => Some(Arc::new(sink)),
+            Err(err) => {
+                tracing::warn!("syslog access log disabled: {err:#}");
+                None
+            }
+        });
+
+    let aliases = Arc::new(alias::Generator::from_config(&config)?);
+    let title_aliases = if config.auto_title_alias {
+        Some(Arc::new(alias::TitleAliases::new(&config)?))
+    } else {
+        None
+    };
+    let thumbnails = thumbnails::Thumbnails::from_config(&config)?.map(Arc::new);
+    let signing_keys = Arc::new(keys::SigningKeys::new(&config));
+    let refreshed_keys = signing_keys.clone();
+    db::interact(&pool, move |conn| refreshed_keys.refresh(conn)).await?;
+    tokio::spawn(keys::refresh_periodically(
+        pool.clone(),
+        signing_keys.clone(),
+    ));
+    let port = config.port;
+    let http_port = config.http_port;
+    let https_origin = https_origin(&config);
+    // Streamed responses are produced quickly and then enforce
+    // `streaming_timeout_secs` while their bodies are sent.
+    let timeout_layer = TimeoutLayer::new(Duration::from_secs(config.request_timeout_secs));
+    let body_limit_layer = RequestBodyLimitLayer::new(config.max_body_bytes);
+    let import_body_limit_layer = RequestBodyLimitLayer::new(config.max_import_body_bytes);
+    let csp_layer = csp::layer(HeaderValue::from_str(&config.csp_directives)?);
+    let state = AppState {
+        pool,
+        read_pool,
+        config: Arc::new(config),
+        syslog,
+        clicks_tx: broadcast::channel(CLICK_EVENTS_CAPACITY).0,
+        create_limiter: Arc::default(),
+        redirect_limiter: Arc::default(),
+        pin_attempts: Arc::default(),
+        form_dedup: Arc::default(),
+        aliases,
+        signing_keys,
+        title_aliases,
+        link_check_client,
+        metadata_client,
+        thumbnails,
+    };
+
+    let api = Router::new()
+        .route("/links/qr-batch", get(qr_batch))
+        .route("/links", get(api::list_links))
+        .route("/links/by-url", get(api::links_by_url))
+        .route("/links/bulk-tag", post(tags::bulk_tag))
+        // The router allows one parameter name per segment; this one is a link id.
+        .route("/links/:alias/heatmap", get(clicks::click_heatmap))
+        .route("/links/expiring.ics", get(expiry::expiring_links_calendar))
+        // Updates count towards the allowance too, since the same call creates.
+        .route(
+            "/links/:alias",
+            put(api::upsert_link).route_layer(middleware::from_fn_with_state(
+                state.clone(),
+                rate_limit::limit_link_creation,
+            )),
+        )
+        .route("/link-patterns", post(patterns::create_pattern))
+        .route("/link-templates", post(patterns::create_template))
+        .route("/clicks", post(clicks::ingest_clicks))
+        .route("/tags", get(tags::list_tags).post(tags::create_tag))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_scope,
+        ))
+        .route("/auth/token", post(auth::issue_token));
+
+    let admin_api = Router::new()
+        .route("/admin/explain", get(admin::explain_query))
+        .route("/admin/alert-rules.yaml", get(metrics::alert_rules))
+        .route("/admin/rollup", post(clicks::roll_up_now))
+        .route("/admin/backup", post(admin::backup))
+        .route("/admin/tags/:id/invite", post(invites::create_invite))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_scope,
+        ));
+
+    // Imports get a larger body limit than the rest of the app.
+    let imports = Router::new()
+        .route("/admin/import/csv", post(import::import_csv))
+        .route("/admin/import/json", post(import::import_json))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_scope,
+        ))
+        .layer(DefaultBodyLimit::disable())
+        .layer(import_body_limit_layer);
+
+    let exports = Router::new()
+        .route("/links/export.ndjson", get(api::export_ndjson))
+        .route("/links/export.csv", get(api::export_csv))
+        .route("/ws/clicks", get(clicks::click_stream))
+        .route("/links/:id/clicks.csv", get(clicks::export_csv))
+        .route("/links/:id/clicks", get(clicks::click_stats))
+        .route(
+            "/links/:id/refresh-metadata",
+            post(metadata::refresh_metadata),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_scope,
+        ));
+
+    // The routes browsers reach with the session cookie.
+    let forms = Router::new()
+        .route(
+            "/links",
+            post(create_link)
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    rate_limit::limit_link_creation,
+                ))
+                // Outside the rate limit, so a repeat doesn't use up the allowance.
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    form_dedup::dedup_forms,
+                )),
+        )
+        .route("/links/:id", delete(delete_link))
+        .route("/links/:id/click-count", get(link_click_count))
+        .route("/links/:id/thumbnail.png", get(thumbnails::serve))
+        .route("/links/:id/recheck", post(link_check::recheck))
+        .route("/login", get(users::login_page).post(users::login))
+        .route("/login/totp", get(totp::verify_page).post(totp::verify))
+        .route("/account/totp", get(totp::enroll_page).post(totp::enroll))
+        .route("/logout", post(users::logout))
+        .route("/consent", post(consent::record))
+        .route(
+            "/preferences/columns",
+            get(preferences::get_columns).post(preferences::set_columns),
+        )
+        .route("/admin", get(admin::dashboard))
+        .route("/admin/links/:id/transfer", post(admin::transfer_link))
+        .route("/admin/impersonate/stop", post(users::stop_impersonating))
+        .route("/admin/impersonate/:user_id", post(admin::impersonate))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            csrf::verify_token,
+        ));
 
     let app = Router::new()
         .route("/", get(root_handler))
         .route("/favicon.ico", get(favicon))
-        .route("/:alias", get(redirect_to_target))
-        .route("/links", post(create_link))
-        .route("/links/:id", delete(delete_link))
-        .with_state(pool);
+        .route("/root.js", get(root_script))
+        .route("/bookmarklet.js", get(bookmarklet_script))
+        .route("/check", get(api::check_url))
+        .route("/metrics", get(metrics::metrics_handler))
+        .route("/:alias", get(redirect_to_target).post(redirect_to_target))
+        .route("/:alias/preview", get(preview_link))
+        .route("/g/:token", get(invites::view_invite))
+        .merge(forms)
+        .merge(exports)
+        .merge(admin_api)
+        .nest("/api", api)
+        // Replaces the extractors' own limit, so the one below is what applies.
+        .layer(DefaultBodyLimit::disable())
+        .layer(body_limit_layer)
+        .merge(imports)
+        .layer(middleware::map_response(body_too_large))
+        .layer(session_layer)
+        .layer(csp_layer)
+        .layer(timeout_layer)
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            telemetry::trace_request,
+        ))
+        .with_state(state);
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], 8080));
-    Server::bind(&addr).serve(app.into_make_service()).await?;
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    match tls_paths {
+        Some((cert_path, key_path)) => {
+            let tls_config = RustlsConfig::from_pem_file(cert_path, key_path).await?;
+            let https = axum_server::bind_rustls(addr, tls_config)
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>());
+            match http_port {
+                Some(http_port) => {
+                    let http_addr = SocketAddr::from(([0, 0, 0, 0], http_port));
+                    let redirect_app = Router::new()
+                        .fallback(redirect_to_https)
+                        .with_state(https_origin);
+                    let http = axum_server::bind(http_addr).serve(redirect_app.into_make_service());
+                    tokio::try_join!(https, http)?;
+                }
+                None => https.await?,
+            }
+        }
+        None => {
+            Server::bind(&addr)
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                .await?
+        }
+    }
     Ok(())
 }
 
+/// The origin plain HTTP requests are redirected to: `base_url`'s host over
+/// HTTPS. If `base_url` is plain HTTP its port is too, so ito's own TLS port
+/// is used instead.
+fn https_origin(config: &Config) -> String {
+    let mut url = config.base_url.clone();
+    if url.scheme() != "https" {
+        // Both only fail for URLs that can't be a base, which config rules out.
+        let _ = url.set_scheme("https");
+        let _ = url.set_port(Some(config.port));
+    }
+    url.origin().ascii_serialization()
+}
+
+/// Sends plain HTTP requests to the same path over HTTPS. The Host header is
+/// ignored, so a request can't be redirected to some other site.
+async fn redirect_to_https(State(https_origin): State<String>, uri: Uri) -> impl IntoResponse {
+    let path_and_query = uri.path_and_query().map_or("/", |pq| pq.as_str());
+    (
+        StatusCode::MOVED_PERMANENTLY,
+        [(header::LOCATION, format!("{https_origin}{path_and_query}"))],
+    )
+}
+
+#[derive(Debug)]
 struct ItoError {
     err: anyhow::Error,
     sc: StatusCode,
@@ -67,13 +504,98 @@ where
     }
 }
 
-type ItoPool = Pool<SqliteConnectionManager>;
+/// An `ItoError` rendered as a JSON body, for handlers that speak JSON.
+struct ItoJsonError(ItoError);
+
+impl IntoResponse for ItoJsonError {
+    fn into_response(self) -> Response {
+        let body = Json(json!({ "error": self.0.err.to_string() }));
+        (self.0.sc, body).into_response()
+    }
+}
+
+/// Gives requests rejected by the body limits a JSON error, like the API's.
+async fn body_too_large(response: Response) -> Response {
+    if response.status() != StatusCode::PAYLOAD_TOO_LARGE {
+        return response;
+    }
+    ItoJsonError(ItoError {
+        err: anyhow!("Request body too large"),
+        sc: StatusCode::PAYLOAD_TOO_LARGE,
+    })
+    .into_response()
+}
+
+impl<E> From<E> for ItoJsonError
+where
+    E: Into<ItoError>,
+{
+    fn from(err: E) -> Self {
+        Self(err.into())
+    }
+}
+
+type ItoPool = deadpool_sqlite::Pool;
+
+/// Connections that can only read, for handlers that never write, so they
+/// don't wait behind writes for the lone `ItoPool` connection.
+#[derive(Clone)]
+struct ReadPool(ItoPool);
+
+#[derive(Clone, FromRef)]
+struct AppState {
+    /// The write pool.
+    pool: ItoPool,
+    read_pool: ReadPool,
+    config: Arc<Config>,
+    syslog: Option<Arc<SyslogSink>>,
+    clicks_tx: broadcast::Sender<ClickEvent>,
+    create_limiter: Arc<RateLimiter>,
+    redirect_limiter: Arc<RedirectLimiter>,
+    pin_attempts: Arc<PinAttempts>,
+    form_dedup: Arc<form_dedup::FormDedup>,
+    aliases: Arc<alias::Generator>,
+    signing_keys: Arc<keys::SigningKeys>,
+    title_aliases: Option<Arc<alias::TitleAliases>>,
+    link_check_client: reqwest::Client,
+    metadata_client: ssrf::PublicClient,
+    thumbnails: Option<Arc<thumbnails::Thumbnails>>,
+}
 
 #[derive(Template)]
 #[template(path = "root.html")]
 #[allow(dead_code)]
 struct RootTemplate {
+    username: String,
     links: Vec<Link>,
+    csrf_token: String,
+    active_tag: Option<String>,
+    /// Without a trailing slash, so `{{base_url}}/{{alias}}` is a short URL.
+    base_url: String,
+    /// What the create form is filled in with, from the bookmarklet.
+    prefill: RootPrefill,
+    show_consent_banner: bool,
+    reused_alias: Option<String>,
+    allow_analytics_snippets: bool,
+    impersonated_by: Option<String>,
+    columns: preferences::ShownColumns,
+}
+
+#[derive(Default, Deserialize)]
+struct RootPrefill {
+    #[serde(default)]
+    target_url: String,
+    #[serde(default)]
+    description: String,
+}
+
+impl RootTemplate {
+    /// Tag names compare case-insensitively, as they do in the database.
+    fn is_active_tag(&self, tag: &str) -> bool {
+        self.active_tag
+            .as_deref()
+            .is_some_and(|active_tag| active_tag.eq_ignore_ascii_case(tag))
+    }
 }
 
 #[allow(dead_code)]
@@ -81,6 +603,28 @@ struct Link {
     id: i64,
     alias: String,
     target_url: Url,
+    expires_at: Option<String>,
+    description_html: Option<String>,
+    remaining_clicks: Option<u64>,
+    tags: Vec<String>,
+    reachability: Reachability,
+    has_thumbnail: bool,
+    created_at: Option<DateTime<Utc>>,
+}
+
+/// Links with this many clicks left or fewer are flagged in the dashboard.
+const LOW_REMAINING_CLICKS: u64 = 10;
+
+impl Link {
+    fn low_on_clicks(&self) -> bool {
+        self.remaining_clicks
+            .is_some_and(|remaining_clicks| remaining_clicks <= LOW_REMAINING_CLICKS)
+    }
+}
+
+/// How many more times a link may be followed before it stops redirecting.
+fn remaining_clicks(max_clicks: Option<i64>, click_count: i64) -> Option<u64> {
+    max_clicks.map(|max_clicks| max_clicks.saturating_sub(click_count).max(0) as u64)
 }
 
 struct HtmlTemplate<T>(T);
@@ -101,65 +645,1006 @@ where
     }
 }
 
-async fn root_handler(State(pool): State<ItoPool>) -> Result<impl IntoResponse, ItoError> {
-    let conn = pool.get()?;
-    let mut statement = conn.prepare("SELECT id, alias, target_url from links")?;
-    let links_rows = statement.query_map([], |row| {
-        Ok(Link {
-            id: row.get(0)?,
-            alias: row.get(1)?,
-            target_url: row.get(2)?,
-        })
-    })?;
-    let mut links = Vec::new();
-    for link in links_rows {
-        links.push(link?);
-    }
-    let template = RootTemplate { links };
-    Ok(HtmlTemplate(template))
+/// Lists the links a user can see. Unless `?3` is NULL, only links tagged
+/// `?3` or one of its descendants are listed.
+const LIST_LINKS_SQL: &str = "WITH RECURSIVE filter_tags (id) AS (
+        SELECT id FROM tags WHERE name = ?3
+        UNION
+        SELECT tags.id FROM tags JOIN filter_tags ON tags.parent_id = filter_tags.id
+    )
+    SELECT id, alias, target_url, expires_at, description, max_clicks, click_count,
+        last_checked_at IS NOT NULL, last_check_status,
+        thumbnail_png IS NOT NULL OR thumbnail_url IS NOT NULL, created_at
+    FROM links WHERE (?1 OR user_id = ?2) AND (?3 IS NULL OR EXISTS (
+        SELECT 1 FROM link_tags
+        WHERE link_tags.link_id = links.id AND link_tags.tag_id IN (SELECT id FROM filter_tags)
+    ))";
+
+#[derive(Deserialize)]
+struct RootParams {
+    tag: Option<String>,
+    /// Set after creating a link with a recently deleted alias.
+    reused_alias: Option<String>,
+    #[serde(flatten)]
+    prefill: RootPrefill,
+}
+
+async fn root_handler(
+    State(ReadPool(pool)): State<ReadPool>,
+    State(config): State<Arc<Config>>,
+    user: User,
+    csrf: CsrfToken,
+    headers: HeaderMap,
+    Query(params): Query<RootParams>,
+) -> Result<impl IntoResponse, ItoError> {
+    let (is_admin, user_id) = (user.is_admin, user.id);
+    let active_tag = params.tag.filter(|tag| !tag.is_empty());
+    let tag = active_tag.clone();
+    let (links, columns) = db::interact(&pool, move |conn| {
+        let links = metrics::time_query(QueryType::SelectLinksList, || {
+            let mut tags = conn.prepare(api::LINK_TAGS_SQL)?;
+            let mut statement = conn.prepare(LIST_LINKS_SQL)?;
+            let links_rows = statement.query_map(params![is_admin, user_id, tag], |row| {
+                let id = row.get(0)?;
+                Ok(Link {
+                    id,
+                    alias: row.get(1)?,
+                    target_url: row.get(2)?,
+                    expires_at: row.get(3)?,
+                    description_html: row
+                        .get::<_, Option<String>>(4)?
+                        .map(|description| render_markdown(&description)),
+                    remaining_clicks: remaining_clicks(row.get(5)?, row.get(6)?),
+                    tags: tags
+                        .query_map([id], |row| row.get(0))?
+                        .collect::<rusqlite::Result<_>>()?,
+                    reachability: Reachability::from_check(row.get(7)?, row.get(8)?),
+                    has_thumbnail: row.get(9)?,
+                    created_at: row
+                        .get::<_, Option<String>>(10)?
+                        .and_then(|created_at| DateTime::parse_from_rfc3339(&created_at).ok())
+                        .map(|created_at| created_at.with_timezone(&Utc)),
+                })
+            })?;
+            links_rows.collect::<rusqlite::Result<Vec<_>>>()
+        })?;
+        Ok::<_, ItoError>((links, preferences::shown_columns(conn, user_id)?))
+    })
+    .await?;
+    let template = RootTemplate {
+        username: user.username,
+        links,
+        csrf_token: csrf.to_string(),
+        active_tag,
+        base_url: config.base_url.as_str().trim_end_matches('/').to_string(),
+        prefill: params.prefill,
+        show_consent_banner: consent::needs_asking(&headers),
+        reused_alias: params.reused_alias,
+        allow_analytics_snippets: config.allow_custom_analytics_snippets && user.is_admin,
+        impersonated_by: user.impersonated_by,
+        columns,
+    };
+    Ok((csrf, HtmlTemplate(template)))
 }
 
 #[derive(Deserialize, Debug)]
 struct CreateLinkInput {
     alias: String,
     target_url: Url,
+    // Empty form fields arrive as empty strings rather than being omitted.
+    #[serde(default)]
+    expires_at: String,
+    #[serde(default)]
+    notify_email: String,
+    #[serde(default)]
+    cache_control: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    max_clicks: String,
+    #[serde(default)]
+    redirect_delay_secs: String,
+    #[serde(default)]
+    dedup_window_secs: String,
+    #[serde(default)]
+    response_content_type: String,
+    #[serde(default)]
+    analytics_snippet: String,
+    #[serde(default)]
+    access_pin: String,
+}
+
+/// Renders a Markdown link description to HTML that is safe to embed in a page.
+fn render_markdown(input: &str) -> String {
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, pulldown_cmark::Parser::new(input));
+    ammonia::clean(&html)
+}
+
+/// The media types a link's redirect response may be given.
+const RESPONSE_CONTENT_TYPES: &[&str] = &[
+    "application/json",
+    "application/xml",
+    "text/html",
+    "text/plain",
+];
+
+/// Checks a link's `Content-Type` for its redirect response against
+/// `RESPONSE_CONTENT_TYPES`. Parameters, like a charset, may follow the type.
+/// Empty input means the default.
+fn parse_response_content_type(input: &str) -> Result<Option<String>, ItoError> {
+    if input.is_empty() {
+        return Ok(None);
+    }
+    let media_type = input.split(';').next().unwrap_or_default().trim();
+    let allowed = RESPONSE_CONTENT_TYPES
+        .iter()
+        .any(|allowed| allowed.eq_ignore_ascii_case(media_type));
+    if !allowed || HeaderValue::from_str(input).is_err() {
+        return Err(ItoError {
+            err: anyhow!(
+                "unsupported Content-Type {input:?}, expected one of {}",
+                RESPONSE_CONTENT_TYPES.join(", ")
+            ),
+            sc: StatusCode::BAD_REQUEST,
+        });
+    }
+    Ok(Some(input.to_string()))
+}
+
+/// Parses an RFC 3339 timestamp, or the `YYYY-MM-DDTHH:MM` of a
+/// `datetime-local` input taken to be UTC. Empty input means "no timestamp".
+fn parse_optional_timestamp(input: &str) -> Result<Option<DateTime<Utc>>, ItoError> {
+    if input.is_empty() {
+        return Ok(None);
+    }
+    DateTime::parse_from_rfc3339(input)
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .or_else(|_| NaiveDateTime::parse_from_str(input, "%Y-%m-%dT%H:%M").map(|t| t.and_utc()))
+        .map(Some)
+        .map_err(|err| ItoError {
+            err: anyhow!("invalid timestamp {input:?}: {err}"),
+            sc: StatusCode::BAD_REQUEST,
+        })
+}
+
+/// The alias a link gets in content-addressed mode: the start of the SHA-256
+/// of its target, so everyone shortening the same URL shares one link.
+fn content_address(target_url: &Url) -> String {
+    let digest = format!("{:x}", Sha256::digest(target_url.as_str()));
+    digest[..8].to_string()
+}
+
+/// How many short links deep `redirects_back_to` follows a target.
+const MAX_REDIRECT_CHAIN: usize = 10;
+
+/// Whether following `target_url` through other short links leads back to
+/// `alias`, which would send browsers around in a loop.
+fn redirects_back_to(
+    conn: &Connection,
+    config: &Config,
+    alias: &str,
+    target_url: &Url,
+) -> rusqlite::Result<bool> {
+    let mut target_url = target_url.clone();
+    for _ in 0..MAX_REDIRECT_CHAIN {
+        let Some(next_alias) = config.alias_of(&target_url) else {
+            return Ok(false);
+        };
+        if next_alias.to_lowercase() == alias.to_lowercase() {
+            return Ok(true);
+        }
+        match conn
+            .query_row(
+                "SELECT target_url FROM links WHERE alias = ? COLLATE NOCASE",
+                [next_alias],
+                |row| row.get(0),
+            )
+            .optional()?
+        {
+            Some(next_target_url) => target_url = next_target_url,
+            None => return Ok(false),
+        }
+    }
+    Ok(false)
+}
+
+/// Rejects targets on hosts that `Config::target_domain_permitted` rules out.
+fn check_target_domain(config: &Config, target_url: &Url) -> Result<(), ItoError> {
+    if config.target_domain_permitted(target_url) {
+        return Ok(());
+    }
+    Err(ItoError {
+        err: anyhow!(
+            "links to {} are not allowed",
+            target_url.host_str().unwrap_or(target_url.scheme())
+        ),
+        sc: StatusCode::BAD_REQUEST,
+    })
 }
 
+/// The error for a link that `redirects_back_to` itself.
+fn redirect_loop_error(alias: &str) -> ItoError {
+    ItoError {
+        err: anyhow!("{alias} would redirect in a loop back to itself"),
+        sc: StatusCode::BAD_REQUEST,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn create_link(
     State(pool): State<ItoPool>,
-    Form(input): Form<CreateLinkInput>,
-) -> Result<impl IntoResponse, ItoError> {
-    let conn = pool.get()?;
-    conn.execute(
-        "INSERT INTO links (alias, target_url) VALUES (?1, ?2)",
-        params![input.alias, input.target_url],
+    State(config): State<Arc<Config>>,
+    State(aliases): State<Arc<alias::Generator>>,
+    State(title_aliases): State<Option<Arc<alias::TitleAliases>>>,
+    State(thumbnails): State<Option<Arc<thumbnails::Thumbnails>>>,
+    user: User,
+    headers: HeaderMap,
+    Form(mut input): Form<CreateLinkInput>,
+) -> Result<Response, ItoError> {
+    let idempotency_key = idempotency::key(&headers)?;
+    input.alias = alias::normalize(&input.alias).map_err(|err| ItoError {
+        err,
+        sc: StatusCode::BAD_REQUEST,
+    })?;
+    check_target_domain(&config, &input.target_url)?;
+    let expires_at = parse_optional_timestamp(&input.expires_at)?.map(db::format_timestamp);
+    let notify_email = match input.notify_email.as_str() {
+        "" => None,
+        email => Some(email.parse::<Address>().map_err(|err| ItoError {
+            err: err.into(),
+            sc: StatusCode::BAD_REQUEST,
+        })?),
+    };
+    let cache_control = match input.cache_control.as_str() {
+        "" => None,
+        value => Some(HeaderValue::from_str(value).map_err(|err| ItoError {
+            err: anyhow!("invalid Cache-Control value: {err}"),
+            sc: StatusCode::BAD_REQUEST,
+        })?),
+    };
+    let max_clicks = match input.max_clicks.as_str() {
+        "" => None,
+        max_clicks => Some(max_clicks.parse::<u32>().map_err(|err| ItoError {
+            err: anyhow!("invalid max clicks {max_clicks:?}: {err}"),
+            sc: StatusCode::BAD_REQUEST,
+        })?),
+    };
+    let cache_control = cache_control
+        .as_ref()
+        .map(|value| value.to_str().map(str::to_string))
+        .transpose()?;
+    let redirect_delay_secs = match input.redirect_delay_secs.as_str() {
+        "" => None,
+        delay => Some(delay.parse::<u32>().map_err(|err| ItoError {
+            err: anyhow!("invalid redirect delay {delay:?}: {err}"),
+            sc: StatusCode::BAD_REQUEST,
+        })?),
+    };
+    let dedup_window_secs = match input.dedup_window_secs.as_str() {
+        "" => None,
+        window => Some(window.parse::<u32>().map_err(|err| ItoError {
+            err: anyhow!("invalid unique click window {window:?}: {err}"),
+            sc: StatusCode::BAD_REQUEST,
+        })?),
+    };
+    let response_content_type = parse_response_content_type(&input.response_content_type)?;
+    let analytics_snippet = match input.analytics_snippet.trim() {
+        "" => None,
+        _ if !config.allow_custom_analytics_snippets => {
+            return Err(ItoError {
+                err: anyhow!("analytics snippets are not enabled"),
+                sc: StatusCode::BAD_REQUEST,
+            })
+        }
+        _ if !user.is_admin => {
+            return Err(ItoError {
+                err: anyhow!("only admins can add analytics snippets"),
+                sc: StatusCode::FORBIDDEN,
+            })
+        }
+        snippet => {
+            analytics::validate_snippet(snippet).map_err(|err| ItoError {
+                err,
+                sc: StatusCode::BAD_REQUEST,
+            })?;
+            Some(snippet.to_string())
+        }
+    };
+    let access_password_hash = match input.access_pin.as_str() {
+        "" => None,
+        access_pin => Some(pin::hash(access_pin.to_string()).await?),
+    };
+    let title_alias = match &title_aliases {
+        Some(title_aliases) if input.alias.is_empty() && !config.content_addressed => {
+            title_aliases.suggest(&input.target_url).await
+        }
+        _ => None,
+    };
+    let user_id = user.id;
+    let target_url = input.target_url.clone();
+    let (response, created_id) = db::interact(&pool, move |conn| {
+        // Immediate, so no other link can be pointed at this one between the
+        // loop check and the insert.
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        // A retried request gets the link the first attempt created.
+        if let Some(key) = &idempotency_key {
+            if let Some(outcome) = idempotency::lookup(&tx, key, user_id)? {
+                return Ok((outcome.into_response(), None));
+            }
+        }
+        let alias = match input.alias.as_str() {
+            "" if config.content_addressed => {
+                let alias = content_address(&input.target_url);
+                let existing: bool = tx.query_row(
+                    "SELECT EXISTS (
+                        SELECT 1 FROM links WHERE alias = ?1 COLLATE NOCASE AND target_url = ?2
+                    )",
+                    params![alias, input.target_url],
+                    |row| row.get(0),
+                )?;
+                if existing {
+                    let link = api::load_link(&tx, &alias, config.timestamp_precision)?;
+                    return Ok((Json(link).into_response(), None));
+                }
+                alias
+            }
+            "" => match &title_alias {
+                Some(title_alias) => {
+                    alias::unused_with_suffix(&tx, title_alias, config.alias_max_retries)?
+                }
+                None => aliases.unused(&tx, config.alias_max_retries)?,
+            },
+            alias => alias.to_string(),
+        };
+        if redirects_back_to(&tx, &config, &alias, &input.target_url)? {
+            return Err(redirect_loop_error(&alias));
+        }
+        metrics::time_query(QueryType::InsertLink, || {
+            tx.execute(
+                "INSERT INTO links (
+                    alias, target_url, user_id, created_at, expires_at, notify_email, cache_control,
+                    description, max_clicks, redirect_delay_secs, dedup_window_secs,
+                    response_content_type, analytics_snippet, access_password_hash
+                )
+                VALUES (
+                    ?1, ?2, ?3, strftime('%Y-%m-%dT%H:%M:%SZ', 'now'), ?4, ?5, ?6, ?7, ?8, ?9, ?10,
+                    ?11, ?12, ?13
+                )",
+                params![
+                    alias,
+                    input.target_url,
+                    user_id,
+                    expires_at,
+                    notify_email.map(|email| email.to_string()),
+                    cache_control,
+                    Some(input.description).filter(|description| !description.is_empty()),
+                    max_clicks,
+                    redirect_delay_secs,
+                    dedup_window_secs,
+                    response_content_type,
+                    analytics_snippet,
+                    access_password_hash,
+                ],
+            )
+        })
+        .map_err(handle_sqlite_err)?;
+        let link_id = tx.last_insert_rowid();
+        let reused = recently_deleted(&tx, &alias, config.alias_reuse_warning_days)?;
+        // Answered with the link rather than a redirect, so a retry can get
+        // exactly the same response.
+        if let Some(key) = &idempotency_key {
+            let link = api::load_link(&tx, &alias, config.timestamp_precision)?;
+            let outcome = idempotency::Outcome {
+                response_body: serde_json::to_string(&link)?,
+                alias_reused: reused,
+            };
+            idempotency::store(&tx, key, user_id, &outcome)?;
+            tx.commit()?;
+            return Ok((outcome.into_response(), Some(link_id)));
+        }
+        tx.commit()?;
+        let response = if reused {
+            let location = url::form_urlencoded::Serializer::for_suffix(String::from("/?"), 2)
+                .append_pair("reused_alias", &alias)
+                .finish();
+            ([("x-ito-alias-reused", "true")], Redirect::to(&location)).into_response()
+        } else {
+            Redirect::to("/").into_response()
+        };
+        Ok((response, Some(link_id)))
+    })
+    .await?;
+    if let (Some(thumbnails), Some(link_id)) = (&thumbnails, created_id) {
+        thumbnails.capture(pool, link_id, target_url);
+    }
+    Ok(response)
+}
+
+/// Whether a link called `alias` was deleted within the last `days` days.
+fn recently_deleted(conn: &Connection, alias: &str, days: u32) -> rusqlite::Result<bool> {
+    if days == 0 {
+        return Ok(false);
+    }
+    conn.query_row(
+        "SELECT EXISTS (
+            SELECT 1 FROM audit_log
+            WHERE action = 'delete_link'
+                AND json_extract(details, '$.alias') = ?1 COLLATE NOCASE
+                AND created_at > strftime('%Y-%m-%dT%H:%M:%SZ', 'now', ?2)
+        )",
+        params![alias, format!("-{days} days")],
+        |row| row.get(0),
     )
-    .map_err(handle_sqlite_err)?;
-    return Ok(Redirect::to("/"));
 }
 
+/// A link's click count as plain text, which the root page fetches for each
+/// link as it scrolls into view.
+async fn link_click_count(
+    State(ReadPool(pool)): State<ReadPool>,
+    user: User,
+    Path(link_id): Path<i64>,
+) -> Result<impl IntoResponse, ItoError> {
+    let click_count = db::interact(&pool, move |conn| {
+        let (click_count, owner_id): (i64, Option<i64>) = conn
+            .query_row(
+                "SELECT click_count, user_id FROM links WHERE id = ?",
+                [link_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .map_err(handle_sqlite_err)?;
+        if !user.can_modify(owner_id) {
+            return Err(ItoError {
+                err: anyhow!("link {link_id} belongs to another user"),
+                sc: StatusCode::FORBIDDEN,
+            });
+        }
+        Ok(click_count)
+    })
+    .await?;
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+        click_count.to_string(),
+    ))
+}
+
+#[derive(Deserialize)]
+struct DeleteLinkParams {
+    #[serde(default)]
+    force: bool,
+}
+
+/// Deletes a link, unless other links point at its short URL and `?force=true`
+/// wasn't given.
 async fn delete_link(
     State(pool): State<ItoPool>,
+    State(config): State<Arc<Config>>,
+    State(redirect_limiter): State<Arc<RedirectLimiter>>,
+    user: User,
     Path(link_id): Path<i64>,
+    Query(params): Query<DeleteLinkParams>,
 ) -> Result<(), ItoError> {
-    let conn = pool.get()?;
-    conn.execute("DELETE FROM links WHERE id = ?", [link_id])?;
+    let alias = db::interact(&pool, move |conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let (alias, owner_id): (String, Option<i64>) = tx
+            .query_row(
+                "SELECT alias, user_id FROM links WHERE id = ?",
+                [link_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .map_err(handle_sqlite_err)?;
+        if !user.can_modify(owner_id) {
+            return Err(ItoError {
+                err: anyhow!("link {link_id} belongs to another user"),
+                sc: StatusCode::FORBIDDEN,
+            });
+        }
+        if !params.force {
+            let dependents = links_targeting(&tx, &config, link_id, &alias)?;
+            if !dependents.is_empty() {
+                return Err(ItoError {
+                    err: anyhow!(
+                        "{alias} is the target of {}; delete with ?force=true to break them",
+                        dependents.join(", ")
+                    ),
+                    sc: StatusCode::CONFLICT,
+                });
+            }
+        }
+        metrics::time_query(QueryType::DeleteLink, || {
+            tx.execute("DELETE FROM links WHERE id = ?", [link_id])
+        })?;
+        audit::record(
+            &tx,
+            "delete_link",
+            Some(user.id),
+            Some(link_id),
+            json!({ "alias": alias }),
+        )?;
+        tx.commit()?;
+        Ok(alias)
+    })
+    .await?;
+    // A new link with the same alias starts with a fresh allowance.
+    redirect_limiter.forget(&alias);
     Ok(())
 }
 
+/// The aliases of other links whose target is the short URL of `alias`.
+fn links_targeting(
+    conn: &Connection,
+    config: &Config,
+    link_id: i64,
+    alias: &str,
+) -> rusqlite::Result<Vec<String>> {
+    // LIKE narrows it down to short URLs; `alias_of` does the exact match.
+    let prefix = config.base_url.as_str().trim_end_matches('/');
+    let pattern = format!(
+        "{}/%",
+        prefix
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_")
+    );
+    let mut statement = conn.prepare(
+        "SELECT alias, target_url FROM links WHERE target_url LIKE ?1 ESCAPE '\\' AND id != ?2",
+    )?;
+    let candidates = statement.query_map(params![pattern, link_id], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, Url>(1)?))
+    })?;
+    let mut dependents = Vec::new();
+    for candidate in candidates {
+        let (dependent, target_url) = candidate?;
+        if config
+            .alias_of(&target_url)
+            .is_some_and(|target_alias| target_alias.to_lowercase() == alias.to_lowercase())
+        {
+            dependents.push(dependent);
+        }
+    }
+    Ok(dependents)
+}
+
+const REDIRECT_LOOKUP_SQL: &str =
+    "SELECT id, target_url, expires_at <= strftime('%Y-%m-%dT%H:%M:%SZ', 'now'), cache_control,
+        redirect_delay_secs, alias, og_title, og_description, dedup_window_secs,
+        response_content_type,
+        CASE WHEN user_id IN (SELECT id FROM users WHERE is_admin) THEN analytics_snippet END,
+        access_password_hash
+    FROM links WHERE alias = ? COLLATE NOCASE";
+
+struct RedirectLink {
+    id: i64,
+    alias: String,
+    target_url: Url,
+    expired: bool,
+    cache_control: Option<String>,
+    redirect_delay_secs: Option<u32>,
+    og_title: Option<String>,
+    og_description: Option<String>,
+    dedup_window_secs: Option<u64>,
+    response_content_type: Option<String>,
+    analytics_snippet: Option<String>,
+    access_password_hash: Option<String>,
+}
+
+/// What an alias was found to redirect to.
+enum RedirectTarget {
+    Link(Box<RedirectLink>),
+    Pattern(Url),
+}
+
+/// Follows a short URL, recording server-side failures in `link_errors` for
+/// the admin dashboard.
+// Axum handlers take each extractor as an argument.
+#[allow(clippy::too_many_arguments)]
 async fn redirect_to_target(
+    read_pool: State<ReadPool>,
     State(pool): State<ItoPool>,
+    config: State<Arc<Config>>,
+    syslog: State<Option<Arc<SyslogSink>>>,
+    clicks_tx: State<broadcast::Sender<ClickEvent>>,
+    redirect_limiter: State<Arc<RedirectLimiter>>,
+    signing_keys: State<Arc<keys::SigningKeys>>,
+    pin_attempts: State<Arc<PinAttempts>>,
     Path(link_alias): Path<String>,
+    Query(mut params): Query<RedirectParams>,
+    headers: HeaderMap,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    // The PIN form posts back to the short URL.
+    form: Option<Form<PinForm>>,
+) -> Result<Response, ItoError> {
+    if let Some(Form(form)) = form {
+        params.pin = params.pin.or(form.pin);
+    }
+    let result = follow_alias(
+        read_pool,
+        State(pool.clone()),
+        config,
+        syslog,
+        clicks_tx,
+        redirect_limiter,
+        signing_keys,
+        pin_attempts,
+        Path(link_alias.clone()),
+        Query(params),
+        headers,
+        connect_info,
+    )
+    .await;
+    match result {
+        Ok(response) => Ok(response.into_response()),
+        Err(err) => {
+            if err.sc.is_server_error() {
+                admin::record_link_error(&pool, link_alias, &err).await;
+            }
+            Err(err)
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn follow_alias(
+    State(ReadPool(read_pool)): State<ReadPool>,
+    State(pool): State<ItoPool>,
+    State(config): State<Arc<Config>>,
+    State(syslog): State<Option<Arc<SyslogSink>>>,
+    State(clicks_tx): State<broadcast::Sender<ClickEvent>>,
+    State(redirect_limiter): State<Arc<RedirectLimiter>>,
+    State(signing_keys): State<Arc<keys::SigningKeys>>,
+    State(pin_attempts): State<Arc<PinAttempts>>,
+    Path(link_alias): Path<String>,
+    Query(params): Query<RedirectParams>,
+    headers: HeaderMap,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
 ) -> Result<impl IntoResponse, ItoError> {
-    let conn = pool.get()?;
-    let target_url: Url = conn
-        .query_row_and_then(
-            "SELECT target_url FROM links WHERE alias = ?",
-            [link_alias],
-            |row| row.get(0),
-        )
-        .map_err(handle_sqlite_err)?;
-    Ok(Redirect::to(&target_url.to_string()))
+    let preview =
+        config.enable_preview_mode && matches!(params.preview.as_deref(), Some("1" | "true"));
+    let addr = connect_info.map(|ConnectInfo(addr)| addr);
+    // Path has already percent-decoded the alias; it is stored in NFC.
+    let lookup_alias: String = link_alias.nfc().collect();
+    let lookup_config = config.clone();
+    let redirect = db::interact(&read_pool, move |conn| {
+        let link_alias = lookup_alias;
+        let link: Option<RedirectLink> = metrics::time_query(QueryType::SelectLink, || {
+            conn.query_row_and_then(REDIRECT_LOOKUP_SQL, [&link_alias], |row| {
+                Ok::<_, rusqlite::Error>(RedirectLink {
+                    id: row.get(0)?,
+                    target_url: row.get(1)?,
+                    expired: row.get::<_, Option<bool>>(2)?.unwrap_or(false),
+                    cache_control: row.get(3)?,
+                    redirect_delay_secs: row.get(4)?,
+                    alias: row.get(5)?,
+                    og_title: row.get(6)?,
+                    og_description: row.get(7)?,
+                    dedup_window_secs: row.get(8)?,
+                    response_content_type: row.get(9)?,
+                    analytics_snippet: row.get(10)?,
+                    access_password_hash: row.get(11)?,
+                })
+            })
+        })
+        .optional()?;
+        Ok::<_, ItoError>(match link {
+            Some(link) => Some(RedirectTarget::Link(Box::new(link))),
+            None => match patterns::resolve(conn, &lookup_config, &link_alias)? {
+                Some(target_url) => Some(RedirectTarget::Pattern(target_url)),
+                None => patterns::resolve_template(conn, &lookup_config, &link_alias)?
+                    .map(RedirectTarget::Pattern),
+            },
+        })
+    })
+    .await?;
+
+    let (target_url, cache_control, content_type, interstitial, unlock_cookie) = match redirect {
+        Some(RedirectTarget::Link(link)) => {
+            let link = *link;
+            if link.expired {
+                let sc = StatusCode::from_u16(config.expired_link_status)?;
+                // A 404 is meant to look like the link never existed.
+                let err = if sc == StatusCode::NOT_FOUND {
+                    anyhow!("no link or pattern matches {link_alias}")
+                } else {
+                    anyhow!("link {link_alias} has expired")
+                };
+                return Err(ItoError { err, sc });
+            }
+            // The preview shows the target, so it needs the PIN too.
+            let unlock_cookie = match &link.access_password_hash {
+                Some(password_hash)
+                    if !pin::is_unlocked(&signing_keys, &headers, link.id, password_hash) =>
+                {
+                    let Some(entered) = params.pin else {
+                        return Ok(pin_page(link.alias, false));
+                    };
+                    if let Some(response) = pin_attempts.check(&link.alias) {
+                        return Ok(response);
+                    }
+                    if !pin::verify(entered, password_hash.clone()).await? {
+                        return Ok(pin_page(link.alias, true));
+                    }
+                    Some(pin::unlock_cookie(
+                        &config,
+                        &signing_keys,
+                        link.id,
+                        password_hash,
+                    ))
+                }
+                _ => None,
+            };
+            if preview {
+                let page =
+                    PreviewTemplate::new(link.target_url, link.og_title, link.og_description);
+                return Ok(HtmlTemplate(page).into_response());
+            }
+            if let Some(limit) = config.max_redirects_per_alias_per_hour {
+                if let Some(response) = redirect_limiter.check(&link.alias, limit) {
+                    return Ok(response);
+                }
+            }
+            let short_url = config.short_url(&link.alias)?;
+            let (link_id, click_alias) = (link.id, link.alias);
+            let dedup_window_secs = link
+                .dedup_window_secs
+                .unwrap_or(config.default_dedup_window_secs);
+            let click_headers = headers.clone();
+            let click_ip_hash = addr.map(|addr| clicks::hash_ip(&config.ip_hash_secret, &addr));
+            // Crawlers are redirected, but their clicks aren't counted.
+            let is_bot = !config.count_bot_clicks
+                && headers
+                    .get(header::USER_AGENT)
+                    .and_then(|value| value.to_str().ok())
+                    .is_some_and(|user_agent| clicks::is_bot(user_agent, &config.bot_user_agents));
+            db::interact(&pool, move |conn| {
+                let available = if is_bot {
+                    conn.query_row(
+                        "SELECT max_clicks IS NULL OR click_count < max_clicks FROM links
+                        WHERE id = ?",
+                        [link_id],
+                        |row| row.get(0),
+                    )?
+                } else {
+                    conn.execute(
+                        "UPDATE links SET click_count = click_count + 1
+                        WHERE id = ? AND (max_clicks IS NULL OR click_count < max_clicks)",
+                        [link_id],
+                    )? > 0
+                };
+                if !available {
+                    return Err(ItoError {
+                        err: anyhow!("link {click_alias} has reached its click limit"),
+                        sc: StatusCode::GONE,
+                    });
+                }
+                if is_bot {
+                    metrics::bot_click_suppressed();
+                    return Ok(());
+                }
+                let click = clicks::record(
+                    conn,
+                    link_id,
+                    &click_alias,
+                    &click_headers,
+                    click_ip_hash,
+                    dedup_window_secs,
+                )?;
+                // Sending only fails when nobody is subscribed.
+                let _ = clicks_tx.send(click);
+                Ok(())
+            })
+            .await?;
+            let interstitial = link
+                .redirect_delay_secs
+                .map(|delay_secs| InterstitialTemplate {
+                    target_url: link.target_url.clone(),
+                    short_url,
+                    delay_secs,
+                    og_title: link.og_title,
+                    og_description: link.og_description,
+                    // Snippets stop running if the feature is turned off again,
+                    // or once their author is no longer an admin.
+                    analytics_snippet: link
+                        .analytics_snippet
+                        .filter(|_| config.allow_custom_analytics_snippets),
+                });
+            // A cached redirect would skip the PIN.
+            let cache_control = match link.access_password_hash {
+                Some(_) => Some("private, no-store".to_string()),
+                None => link.cache_control,
+            };
+            (
+                link.target_url,
+                cache_control,
+                link.response_content_type,
+                interstitial,
+                unlock_cookie,
+            )
+        }
+        Some(RedirectTarget::Pattern(target_url)) if preview => {
+            return Ok(HtmlTemplate(PreviewTemplate::new(target_url, None, None)).into_response());
+        }
+        Some(RedirectTarget::Pattern(target_url)) => (target_url, None, None, None, None),
+        None => {
+            return Err(ItoError {
+                err: anyhow!("no link or pattern matches {link_alias}"),
+                sc: StatusCode::NOT_FOUND,
+            })
+        }
+    };
+
+    metrics::redirect_served();
+    if let Some(syslog) = syslog {
+        syslog.log_redirect(&RedirectEvent {
+            alias: &link_alias,
+            target_url: target_url.as_str(),
+            ip_hash: addr.map(|addr| clicks::hash_ip(&config.ip_hash_secret, &addr)),
+            user_agent: headers
+                .get(header::USER_AGENT)
+                .and_then(|value| value.to_str().ok()),
+        });
+    }
+
+    let cache_control = match cache_control {
+        Some(value) => HeaderValue::try_from(value)?,
+        None => config
+            .default_cache_control
+            .clone()
+            .unwrap_or_else(|| HeaderValue::from_static(DEFAULT_REDIRECT_CACHE_CONTROL)),
+    };
+    let cache_control = [(header::CACHE_CONTROL, cache_control)];
+    // A delayed redirect shows a page first and leaves the redirect to the browser.
+    let mut response = match interstitial {
+        Some(page) => {
+            let csp = page
+                .analytics_snippet
+                .as_deref()
+                .map(|snippet| csp::sandboxing_inline_script(&config.csp_directives, snippet))
+                .map(HeaderValue::try_from)
+                .transpose()?;
+            let mut response = (
+                cache_control,
+                [(
+                    header::REFRESH,
+                    format!("{}; url={target_url}", page.delay_secs),
+                )],
+                HtmlTemplate(page),
+            )
+                .into_response();
+            if let Some(csp) = csp {
+                response
+                    .headers_mut()
+                    .insert(header::CONTENT_SECURITY_POLICY, csp);
+            }
+            response
+        }
+        None => (cache_control, Redirect::to(target_url.as_ref())).into_response(),
+    };
+    if let Some(content_type) = content_type {
+        response
+            .headers_mut()
+            .insert(header::CONTENT_TYPE, HeaderValue::try_from(content_type)?);
+    }
+    if let Some(cookie) = unlock_cookie {
+        response
+            .headers_mut()
+            .append(header::SET_COOKIE, HeaderValue::try_from(cookie)?);
+    }
+    Ok(response)
+}
+
+fn pin_page(alias: String, wrong: bool) -> Response {
+    (
+        [(header::CACHE_CONTROL, "private, no-store")],
+        HtmlTemplate(pin::PinTemplate { alias, wrong }),
+    )
+        .into_response()
+}
+
+#[derive(Template)]
+#[template(path = "interstitial.html")]
+struct InterstitialTemplate {
+    target_url: Url,
+    short_url: Url,
+    delay_secs: u32,
+    og_title: Option<String>,
+    og_description: Option<String>,
+    analytics_snippet: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RedirectParams {
+    preview: Option<String>,
+    /// For links with an `access_password_hash`.
+    pin: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PinForm {
+    pin: Option<String>,
+}
+
+/// Where a short URL leads, shown instead of redirecting. Nothing is counted
+/// as a click.
+#[derive(Template)]
+#[template(path = "preview.html")]
+struct PreviewTemplate {
+    target_url: Url,
+    /// The target with percent-encoding undone, for reading.
+    decoded_target_url: String,
+    domain: Option<String>,
+    og_title: Option<String>,
+    og_description: Option<String>,
+}
+
+impl PreviewTemplate {
+    fn new(target_url: Url, og_title: Option<String>, og_description: Option<String>) -> Self {
+        PreviewTemplate {
+            decoded_target_url: percent_encoding::percent_decode_str(target_url.as_str())
+                .decode_utf8_lossy()
+                .into_owned(),
+            domain: target_url.domain().map(str::to_string),
+            target_url,
+            og_title,
+            og_description,
+        }
+    }
+}
+
+/// `Cache-Control` for redirects when neither the link nor the config sets
+/// one. Redirects are always 303 See Other, which aren't permanent, so they
+/// aren't cached.
+const DEFAULT_REDIRECT_CACHE_CONTROL: &str = "no-store";
+
+#[derive(Serialize)]
+struct LinkPreview {
+    alias: String,
+    target_url: Url,
+    og_title: Option<String>,
+    og_description: Option<String>,
+    created_at: Option<String>,
+    click_count: i64,
+    remaining_clicks: Option<u64>,
+}
+
+async fn preview_link(
+    State(ReadPool(pool)): State<ReadPool>,
+    State(config): State<Arc<Config>>,
+    Path(link_alias): Path<String>,
+) -> Result<Json<LinkPreview>, ItoJsonError> {
+    let (preview, protected) = db::interact(&pool, move |conn| {
+        metrics::time_query(QueryType::SelectLink, || {
+            conn.query_row_and_then(
+                "SELECT alias, target_url, created_at, click_count, max_clicks, og_title,
+                    og_description, access_password_hash IS NOT NULL
+                FROM links WHERE alias = ? COLLATE NOCASE",
+                [link_alias.nfc().collect::<String>()],
+                |row| {
+                    let preview = LinkPreview {
+                        alias: row.get(0)?,
+                        target_url: row.get(1)?,
+                        og_title: row.get(5)?,
+                        og_description: row.get(6)?,
+                        created_at: row
+                            .get::<_, Option<String>>(2)?
+                            .map(|created_at| config.timestamp_precision.truncate(created_at)),
+                        click_count: row.get(3)?,
+                        remaining_clicks: remaining_clicks(row.get(4)?, row.get(3)?),
+                    };
+                    Ok((preview, row.get::<_, bool>(7)?))
+                },
+            )
+        })
+        .map_err(handle_sqlite_err)
+    })
+    .await?;
+    // The target is only for visitors who know the PIN.
+    if protected {
+        return Err(ItoJsonError(ItoError {
+            err: anyhow!("link {} is protected by a PIN", preview.alias),
+            sc: StatusCode::FORBIDDEN,
+        }));
+    }
+    Ok(Json(preview))
 }
 
 fn handle_sqlite_err(err: rusqlite::Error) -> ItoError {
@@ -185,6 +1670,398 @@ fn handle_sqlite_err(err: rusqlite::Error) -> ItoError {
     }
 }
 
-async fn favicon() -> StatusCode {
-    StatusCode::NO_CONTENT
+#[derive(Deserialize)]
+struct QrBatchParams {
+    ids: Option<String>,
+}
+
+/// The most `ids` a QR code batch may list, the fewest placeholders any
+/// SQLite build allows in one statement.
+const MAX_QR_BATCH_IDS: usize = 999;
+
+async fn qr_batch(
+    State(ReadPool(pool)): State<ReadPool>,
+    State(config): State<Arc<Config>>,
+    Query(params): Query<QrBatchParams>,
+) -> Result<impl IntoResponse, ItoError> {
+    let ids = match params.ids {
+        Some(ids) => Some(
+            ids.split(',')
+                .map(|id| id.trim().parse::<i64>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|err| ItoError {
+                    err: err.into(),
+                    sc: StatusCode::BAD_REQUEST,
+                })?,
+        ),
+        None => None,
+    };
+    if ids.as_ref().is_some_and(|ids| ids.len() > MAX_QR_BATCH_IDS) {
+        return Err(ItoError {
+            err: anyhow!("at most {MAX_QR_BATCH_IDS} ids can be given"),
+            sc: StatusCode::BAD_REQUEST,
+        });
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(config.streaming_timeout_secs);
+    let conn = pool.get().await?;
+    let (count_tx, count_rx) = oneshot::channel();
+    let (tx, rx) = mpsc::channel(16);
+    tokio::spawn(async move {
+        conn.interact(move |conn| {
+            let mut count_tx = Some(count_tx);
+            if let Err(err) = write_qr_batch(conn, &config, ids, deadline, &mut count_tx, &tx) {
+                match count_tx {
+                    // Nothing has been sent yet, so the request can still fail.
+                    Some(count_tx) => {
+                        let _ = count_tx.send(Err(err));
+                    }
+                    None => {
+                        let _ = tx.blocking_send(Err(io::Error::other(err.to_string())));
+                    }
+                }
+            }
+        })
+        .await
+    });
+    let count = count_rx
+        .await
+        .map_err(|_| anyhow!("QR code batch stopped before it started"))??;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/zip".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"qr-codes.zip\"".to_string(),
+            ),
+            (HeaderName::from_static("x-qr-count"), count.to_string()),
+        ],
+        StreamBody::new(ReceiverStream::new(rx)),
+    ))
+}
+
+/// Counts the links in a QR code batch and sends the count to `count_tx`,
+/// then streams a ZIP archive of their codes to `tx` as each is rendered.
+fn write_qr_batch(
+    conn: &mut Connection,
+    config: &Config,
+    ids: Option<Vec<i64>>,
+    deadline: Instant,
+    count_tx: &mut Option<oneshot::Sender<Result<i64>>>,
+    tx: &mpsc::Sender<io::Result<Bytes>>,
+) -> Result<()> {
+    let ids = ids.map(|ids| {
+        let placeholders = vec!["?"; ids.len()].join(", ");
+        (format!("id IN ({placeholders})"), ids)
+    });
+    let (condition, ids) = ids.unwrap_or_else(|| ("TRUE".to_string(), Vec::new()));
+    // One read transaction, so the count matches the codes that follow it.
+    let snapshot = conn.transaction()?;
+    let count = snapshot.query_row(
+        &format!("SELECT COUNT(*) FROM links WHERE {condition}"),
+        params_from_iter(&ids),
+        |row| row.get(0),
+    )?;
+    if let Some(count_tx) = count_tx.take() {
+        if count_tx.send(Ok(count)).is_err() {
+            return Ok(());
+        }
+    }
+
+    let mut statement = snapshot.prepare(&format!(
+        "SELECT alias FROM links WHERE {condition} ORDER BY id"
+    ))?;
+    let mut rows = statement.query(params_from_iter(&ids))?;
+    let mut archive = qr::ZipStream::new(BufWriter::new(ChannelWriter(tx)));
+    while let Some(row) = rows.next()? {
+        if Instant::now() >= deadline {
+            bail!("QR code batch took longer than ITO_STREAMING_TIMEOUT_SECS");
+        }
+        let alias: String = row.get(0)?;
+        let png = qr::png(config.short_url(&alias)?.as_str())?;
+        archive.add(format!("{alias}.png"), &png)?;
+    }
+    archive.finish()?.flush()?;
+    Ok(())
+}
+
+/// Sends whatever is written to it as chunks of a streamed response body.
+struct ChannelWriter<'a>(&'a mpsc::Sender<io::Result<Bytes>>);
+
+impl Write for ChannelWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .blocking_send(Ok(Bytes::copy_from_slice(buf)))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client went away"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The root page's script, served separately so the page works under a CSP
+/// without `'unsafe-inline'` scripts.
+const ROOT_SCRIPT: &str = include_str!("assets/root.js");
+
+const BOOKMARKLET_SCRIPT: &str = include_str!("assets/bookmarklet.js");
+
+/// The script the bookmarklet on the root page loads, pointed at this server.
+async fn bookmarklet_script(State(config): State<Arc<Config>>) -> impl IntoResponse {
+    let base_url = json!(config.base_url.as_str().trim_end_matches('/')).to_string();
+    (
+        [(
+            header::CONTENT_TYPE,
+            "application/javascript; charset=utf-8",
+        )],
+        BOOKMARKLET_SCRIPT.replace("ITO_BASE_URL", &base_url),
+    )
+}
+
+async fn root_script() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/javascript; charset=utf-8")],
+        ROOT_SCRIPT,
+    )
+}
+
+const FAVICON: &[u8] = include_bytes!("assets/favicon.svg");
+const FAVICON_ETAG_BYTES: [u8; 18] = etag(FAVICON);
+const FAVICON_ETAG: &str = match std::str::from_utf8(&FAVICON_ETAG_BYTES) {
+    Ok(etag) => etag,
+    Err(_) => panic!("ETag is ASCII"),
+};
+
+/// A quoted FNV-1a hash of `bytes`, usable as an ETag and computed at compile time.
+const fn etag(bytes: &[u8]) -> [u8; 18] {
+    const HEX: &[u8; 16] = b"0123456789abcdef";
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash = (hash ^ bytes[i] as u64).wrapping_mul(0x0100_0000_01b3);
+        i += 1;
+    }
+    let mut etag = [b'"'; 18];
+    let mut i = 0;
+    while i < 16 {
+        etag[16 - i] = HEX[(hash >> (4 * i) & 0xf) as usize];
+        i += 1;
+    }
+    etag
+}
+
+async fn favicon(headers: HeaderMap) -> Response {
+    let cache_headers = [
+        (header::ETAG, FAVICON_ETAG),
+        (header::CACHE_CONTROL, "public, max-age=604800"),
+    ];
+    let not_modified = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|tags| {
+            tags.split(',')
+                .any(|tag| matches!(tag.trim().trim_start_matches("W/"), "*" | FAVICON_ETAG))
+        });
+    if not_modified {
+        return (StatusCode::NOT_MODIFIED, cache_headers).into_response();
+    }
+    (
+        cache_headers,
+        [(header::CONTENT_TYPE, "image/svg+xml")],
+        FAVICON,
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn test_pool() -> ItoPool {
+        // Every in-memory connection is its own database, so only allow one.
+        let pool = deadpool_sqlite::Config::new(":memory:")
+            .builder(deadpool_sqlite::Runtime::Tokio1)
+            .unwrap()
+            .max_size(1)
+            .post_create(db::init_hook(false))
+            .build()
+            .unwrap();
+        db::interact(&pool, |conn| {
+            db::migrate(conn)?;
+            conn.execute(
+                "INSERT INTO users (id, username, password_hash, created_at)
+                VALUES (1, 'test', '', '')",
+                [],
+            )?;
+            anyhow::Ok(())
+        })
+        .await
+        .unwrap();
+        pool
+    }
+
+    fn test_user() -> User {
+        User {
+            id: 1,
+            username: "test".to_string(),
+            is_admin: false,
+            impersonated_by: None,
+        }
+    }
+
+    async fn create(pool: &ItoPool, alias: &str, headers: HeaderMap) -> Result<Response, ItoError> {
+        let input = CreateLinkInput {
+            alias: alias.to_string(),
+            target_url: "https://example.com".parse().unwrap(),
+            expires_at: String::new(),
+            notify_email: String::new(),
+            cache_control: String::new(),
+            description: String::new(),
+            max_clicks: String::new(),
+            redirect_delay_secs: String::new(),
+            dedup_window_secs: String::new(),
+            response_content_type: String::new(),
+            analytics_snippet: String::new(),
+            access_pin: String::new(),
+        };
+        let config = Config::from_env().unwrap();
+        let aliases = alias::Generator::from_config(&config).unwrap();
+        create_link(
+            State(pool.clone()),
+            State(Arc::new(config)),
+            State(Arc::new(aliases)),
+            State(None),
+            State(None),
+            test_user(),
+            headers,
+            Form(input),
+        )
+        .await
+    }
+
+    #[test]
+    fn sqlite_errors_map_to_statuses() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute("CREATE TABLE t (name TEXT UNIQUE)", [])
+            .unwrap();
+        conn.execute("INSERT INTO t VALUES ('a')", []).unwrap();
+
+        let duplicate = conn.execute("INSERT INTO t VALUES ('a')", []).unwrap_err();
+        assert_eq!(handle_sqlite_err(duplicate).sc, StatusCode::BAD_REQUEST);
+        let missing = conn
+            .query_row("SELECT name FROM t WHERE name = 'b'", [], |row| {
+                row.get::<_, String>(0)
+            })
+            .unwrap_err();
+        assert_eq!(handle_sqlite_err(missing).sc, StatusCode::NOT_FOUND);
+        let invalid = conn.execute("SELECT FROM", []).unwrap_err();
+        assert_eq!(
+            handle_sqlite_err(invalid).sc,
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        let wrong_type = conn
+            .query_row("SELECT name FROM t", [], |row| row.get::<_, i64>(0))
+            .unwrap_err();
+        assert_eq!(
+            handle_sqlite_err(wrong_type).sc,
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[test]
+    fn render_markdown_strips_scripts_and_event_handlers() {
+        let html =
+            render_markdown("*hi* <script>alert(1)</script><a href=\"/x\" onclick=\"y\">x</a>");
+        assert!(html.contains("<em>hi</em>"));
+        assert!(!html.contains("script"));
+        assert!(!html.contains("onclick"));
+    }
+
+    #[tokio::test]
+    async fn redirect_matches_alias_case_insensitively() {
+        let pool = test_pool().await;
+        create(&pool, "MyAlias", HeaderMap::new()).await.unwrap();
+
+        for alias in ["myalias", "MYALIAS", "MyAlias"] {
+            let response = redirect_to_target(
+                State(ReadPool(pool.clone())),
+                State(pool.clone()),
+                State(Arc::new(Config::from_env().unwrap())),
+                State(None),
+                State(broadcast::channel(1).0),
+                State(Arc::default()),
+                State(Arc::new(keys::SigningKeys::new(
+                    &Config::from_env().unwrap(),
+                ))),
+                State(Arc::default()),
+                Path(alias.to_string()),
+                Query(RedirectParams {
+                    preview: None,
+                    pin: None,
+                }),
+                HeaderMap::new(),
+                None,
+                None,
+            )
+            .await
+            .unwrap()
+            .into_response();
+            assert_eq!(response.headers()[header::LOCATION], "https://example.com/");
+        }
+    }
+
+    #[tokio::test]
+    async fn aliases_differing_only_in_case_are_duplicates() {
+        let pool = test_pool().await;
+        create(&pool, "MyAlias", HeaderMap::new()).await.unwrap();
+
+        let err = create(&pool, "myalias", HeaderMap::new())
+            .await
+            .unwrap_err();
+        assert_eq!(err.sc, StatusCode::BAD_REQUEST);
+
+        let aliases: Vec<String> = db::interact(&pool, |conn| {
+            let mut statement = conn.prepare("SELECT alias FROM links")?;
+            let aliases = statement.query_map([], |row| row.get(0))?;
+            anyhow::Ok(aliases.collect::<Result<_, _>>()?)
+        })
+        .await
+        .unwrap();
+        assert_eq!(aliases, ["MyAlias"]);
+    }
+
+    #[tokio::test]
+    async fn idempotent_retries_get_the_original_response() {
+        let pool = test_pool().await;
+        // A link with the alias was just deleted, so creating it again warns.
+        db::interact(&pool, |conn| {
+            conn.execute(
+                "INSERT INTO audit_log (action, details, created_at)
+                VALUES ('delete_link', '{\"alias\": \"retried\"}',
+                    strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))",
+                [],
+            )?;
+            anyhow::Ok(())
+        })
+        .await
+        .unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("idempotency-key", HeaderValue::from_static("first-try"));
+
+        let mut responses = Vec::new();
+        for _ in 0..2 {
+            let response = create(&pool, "retried", headers.clone()).await.unwrap();
+            let (parts, mut body) = response.into_parts();
+            let mut bytes = Vec::new();
+            while let Some(chunk) = http_body::Body::data(&mut body).await {
+                bytes.extend_from_slice(&chunk.unwrap());
+            }
+            responses.push((parts.status, parts.headers, bytes));
+        }
+        let (status, headers, _) = &responses[0];
+        assert_eq!(*status, StatusCode::OK);
+        assert_eq!(headers["x-ito-alias-reused"], "true");
+        assert_eq!(responses[0], responses[1]);
+    }
 }
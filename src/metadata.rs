@@ -0,0 +1,156 @@
+use std::{sync::Arc, sync::LazyLock};
+
+use anyhow::{anyhow, bail, Result};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use regex::Regex;
+use rusqlite::params;
+use url::Url;
+
+use crate::{
+    api::{self, ApiLink},
+    config::Config,
+    db, handle_sqlite_err,
+    ssrf::PublicClient,
+    ItoError, ItoJsonError, ItoPool,
+};
+
+/// How much of a target page is searched for its Open Graph tags, which
+/// belong in the `<head>`.
+const METADATA_SEARCH_BYTES: usize = 64 * 1024;
+
+static META_TAG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?is)<meta\b[^>]*>").expect("meta regex is valid"));
+static ATTRIBUTE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?is)([a-z:-]+)\s*=\s*(?:"([^"]*)"|'([^']*)')"#)
+        .expect("attribute regex is valid")
+});
+
+/// A page's Open Graph title and description.
+#[derive(Default)]
+struct Metadata {
+    og_title: Option<String>,
+    og_description: Option<String>,
+}
+
+/// Fetches `url` and reads its `og:title` and `og:description`.
+async fn fetch(client: &PublicClient, url: &Url) -> Result<Metadata> {
+    let mut response = client.get(url)?.send().await?.error_for_status()?;
+    let is_html = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("text/html"));
+    if !is_html {
+        bail!("{url} isn't an HTML page");
+    }
+    let mut page = Vec::new();
+    while page.len() < METADATA_SEARCH_BYTES {
+        match response.chunk().await? {
+            Some(chunk) => page.extend_from_slice(&chunk),
+            None => break,
+        }
+    }
+    Ok(parse(&String::from_utf8_lossy(&page)))
+}
+
+fn parse(page: &str) -> Metadata {
+    let mut metadata = Metadata::default();
+    for tag in META_TAG.find_iter(page) {
+        let (mut property, mut content) = (None, None);
+        for attribute in ATTRIBUTE.captures_iter(tag.as_str()) {
+            let value = attribute
+                .get(2)
+                .or(attribute.get(3))
+                .map_or("", |value| value.as_str());
+            match attribute[1].to_ascii_lowercase().as_str() {
+                // Some pages use `name` where the protocol says `property`.
+                "property" | "name" => property = Some(value.to_ascii_lowercase()),
+                "content" => content = Some(decode_entities(value.trim())),
+                _ => {}
+            }
+        }
+        let content = content.filter(|content| !content.is_empty());
+        match property.as_deref() {
+            Some("og:title") if metadata.og_title.is_none() => metadata.og_title = content,
+            Some("og:description") if metadata.og_description.is_none() => {
+                metadata.og_description = content
+            }
+            _ => {}
+        }
+    }
+    metadata
+}
+
+/// Undoes the escapes that commonly appear in attribute values. Templates
+/// escape the text again when it is shown.
+fn decode_entities(value: &str) -> String {
+    value
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// Fetches link `link_id`'s target again and stores its current Open Graph
+/// title and description, for `Config::fetch_metadata`.
+pub async fn refresh_metadata(
+    State(pool): State<ItoPool>,
+    State(config): State<Arc<Config>>,
+    State(client): State<PublicClient>,
+    Path(link_id): Path<i64>,
+) -> Result<Json<ApiLink>, ItoJsonError> {
+    if !config.fetch_metadata {
+        return Err(ItoJsonError(ItoError {
+            err: anyhow!("metadata fetching is not enabled"),
+            sc: StatusCode::BAD_REQUEST,
+        }));
+    }
+    let (alias, target_url): (String, Url) = db::interact(&pool, move |conn| {
+        conn.query_row(
+            "SELECT alias, target_url FROM links WHERE id = ?",
+            [link_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .map_err(handle_sqlite_err)
+    })
+    .await?;
+    let metadata = fetch(&client, &target_url).await.map_err(|err| ItoError {
+        err: err.context(format!("failed to fetch metadata for link {link_id}")),
+        sc: StatusCode::BAD_GATEWAY,
+    })?;
+    let precision = config.timestamp_precision;
+    let link = db::interact(&pool, move |conn| -> Result<_, ItoError> {
+        conn.execute(
+            "UPDATE links SET og_title = ?1, og_description = ?2,
+                metadata_refreshed_at = strftime('%Y-%m-%dT%H:%M:%SZ', 'now')
+            WHERE id = ?3",
+            params![metadata.og_title, metadata.og_description, link_id],
+        )?;
+        api::load_link(conn, &alias, precision).map_err(handle_sqlite_err)
+    })
+    .await?;
+    Ok(Json(link))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_reads_open_graph_tags_in_any_attribute_order() {
+        let metadata = parse(
+            r#"<head><meta content="Tom &amp; Jerry" property="og:title">
+            <meta property='og:description' content='A cat, a mouse'/>
+            <meta property="og:title" content="ignored"></head>"#,
+        );
+        assert_eq!(metadata.og_title.as_deref(), Some("Tom & Jerry"));
+        assert_eq!(metadata.og_description.as_deref(), Some("A cat, a mouse"));
+        assert!(parse("<title>No tags</title>").og_title.is_none());
+    }
+}
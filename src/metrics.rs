@@ -0,0 +1,217 @@
+use std::{
+    sync::{Arc, LazyLock, OnceLock},
+    time::Instant,
+};
+
+use axum::{extract::State, http::header, response::IntoResponse};
+use prometheus::{
+    exponential_buckets, register_histogram_vec, register_int_counter, register_int_gauge_vec,
+    Encoder, HistogramVec, IntCounter, IntGaugeVec, TextEncoder,
+};
+use tokio::sync::mpsc;
+
+use crate::{config::Config, ItoError, ItoPool, ReadPool};
+
+static DB_QUERY_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "ito_db_query_duration_seconds",
+        "Time spent executing SQLite queries, by query type.",
+        &["query_type"],
+        // 100µs up to ~1.6s; most queries are well under a millisecond.
+        exponential_buckets(0.0001, 4.0, 8).expect("buckets are valid")
+    )
+    .expect("metric is registered once")
+});
+
+static DROPPED_EVENTS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "ito_metric_events_dropped_total",
+        "Metric events dropped because the metrics buffer was full."
+    )
+    .expect("metric is registered once")
+});
+
+static BOT_CLICKS_SUPPRESSED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "ito_bot_clicks_suppressed_total",
+        "Redirects for crawlers that were not recorded as clicks."
+    )
+    .expect("metric is registered once")
+});
+
+static REDIRECTS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "ito_redirects_total",
+        "Redirects served, including those for crawlers."
+    )
+    .expect("metric is registered once")
+});
+
+static DB_POOL_AVAILABLE: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "ito_db_pool_available",
+        "Connections each database pool could still hand out, by pool.",
+        &["pool"]
+    )
+    .expect("metric is registered once")
+});
+
+/// Counts a redirect served to a bot without recording a click.
+pub fn bot_click_suppressed() {
+    BOT_CLICKS_SUPPRESSED.inc();
+}
+
+/// Counts a redirect to a link's or pattern's target.
+pub fn redirect_served() {
+    REDIRECTS.inc();
+}
+
+/// Records how many more connections `pool` could open or hand out.
+/// Connections are opened lazily, so this counts unopened ones too.
+fn record_pool_status(label: &str, pool: &ItoPool) {
+    let status = pool.status();
+    let in_use = status.size as i64 - status.available as i64;
+    DB_POOL_AVAILABLE
+        .with_label_values(&[label])
+        .set((status.max_size as i64 - in_use).max(0));
+}
+
+/// Where handlers send metric events once `start_buffer` has run.
+static BUFFER: OnceLock<mpsc::Sender<MetricEvent>> = OnceLock::new();
+
+/// Something to record in a metric.
+enum MetricEvent {
+    QueryDuration { query_type: QueryType, seconds: f64 },
+}
+
+impl MetricEvent {
+    fn record(self) {
+        match self {
+            MetricEvent::QueryDuration {
+                query_type,
+                seconds,
+            } => DB_QUERY_DURATION
+                .with_label_values(&[query_type.label()])
+                .observe(seconds),
+        }
+    }
+
+    /// Hands the event to the background task, or drops it when the buffer
+    /// is full, so recording metrics never holds up a request. Without a
+    /// buffer, as in tests and CLI commands, it is recorded right away.
+    fn send(self) {
+        match BUFFER.get() {
+            Some(buffer) => {
+                if buffer.try_send(self).is_err() {
+                    DROPPED_EVENTS.inc();
+                }
+            }
+            None => self.record(),
+        }
+    }
+}
+
+/// Starts the task that records metric events, buffering up to `capacity`
+/// of them.
+pub fn start_buffer(capacity: usize) {
+    let (tx, mut rx) = mpsc::channel(capacity);
+    if BUFFER.set(tx).is_err() {
+        return;
+    }
+    // Registered up front so they are exported, as zero, before anything happens.
+    LazyLock::force(&DROPPED_EVENTS);
+    LazyLock::force(&BOT_CLICKS_SUPPRESSED);
+    LazyLock::force(&REDIRECTS);
+    tokio::spawn(async move {
+        while let Some(event) = rx.recv().await {
+            event.record();
+        }
+    });
+}
+
+/// The queries whose latency is tracked in `ito_db_query_duration_seconds`.
+#[derive(Clone, Copy)]
+pub enum QueryType {
+    SelectLink,
+    InsertLink,
+    DeleteLink,
+    InsertClick,
+    SelectLinksList,
+}
+
+impl QueryType {
+    fn label(self) -> &'static str {
+        match self {
+            QueryType::SelectLink => "select_link",
+            QueryType::InsertLink => "insert_link",
+            QueryType::DeleteLink => "delete_link",
+            QueryType::InsertClick => "insert_click",
+            QueryType::SelectLinksList => "select_links_list",
+        }
+    }
+}
+
+/// Runs `query`, recording how long it took under `query_type`.
+pub fn time_query<T>(query_type: QueryType, query: impl FnOnce() -> T) -> T {
+    let started = Instant::now();
+    let result = query();
+    MetricEvent::QueryDuration {
+        query_type,
+        seconds: started.elapsed().as_secs_f64(),
+    }
+    .send();
+    result
+}
+
+/// Serves every registered metric in the Prometheus text format.
+pub async fn metrics_handler(
+    State(pool): State<ItoPool>,
+    State(ReadPool(read_pool)): State<ReadPool>,
+) -> Result<impl IntoResponse, ItoError> {
+    record_pool_status("write", &pool);
+    record_pool_status("read", &read_pool);
+    let encoder = TextEncoder::new();
+    let body = encoder.encode_to_string(&prometheus::gather())?;
+    Ok((
+        [(header::CONTENT_TYPE, encoder.format_type().to_string())],
+        body,
+    ))
+}
+
+/// A Prometheus alerting rule file for the metrics above, with the
+/// thresholds from `Config::alert_thresholds`.
+pub async fn alert_rules(State(config): State<Arc<Config>>) -> impl IntoResponse {
+    let alerts = &config.alert_thresholds;
+    let rules = format!(
+        r#"groups:
+  - name: ito
+    rules:
+      - alert: ItoSlowQueries
+        expr: histogram_quantile(0.99, sum by (le, query_type) (rate(ito_db_query_duration_seconds_bucket[5m]))) > {slow_query_seconds}
+        for: 10m
+        labels:
+          severity: warning
+        annotations:
+          summary: "99th percentile of {{{{ $labels.query_type }}}} queries is over {slow_query_seconds}s"
+      - alert: ItoDatabasePoolExhausted
+        expr: ito_db_pool_available{{pool="read"}} < {min_available_connections}
+        for: 5m
+        labels:
+          severity: critical
+        annotations:
+          summary: "Fewer than {min_available_connections} read connections are free"
+      - alert: ItoNoRedirects
+        expr: sum(rate(ito_redirects_total[5m])) == 0 and on() hour() >= {business_hours_start} and on() hour() < {business_hours_end}
+        for: 30m
+        labels:
+          severity: warning
+        annotations:
+          summary: "No redirects have been served during business hours"
+"#,
+        slow_query_seconds = alerts.slow_query_seconds,
+        min_available_connections = alerts.min_available_connections,
+        business_hours_start = alerts.business_hours_start,
+        business_hours_end = alerts.business_hours_end,
+    );
+    ([(header::CONTENT_TYPE, "application/yaml")], rules)
+}
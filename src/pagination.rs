@@ -0,0 +1,51 @@
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+
+use crate::keys::SigningKeys;
+
+/// The most links a single page can hold.
+pub const MAX_PAGE_SIZE: u32 = 1000;
+
+/// Signed so only the cursor for an id ito actually returned is accepted,
+/// and prefixed so no other signed value can pass as a cursor.
+fn signed_message(id: i64) -> String {
+    format!("cursor:{id}")
+}
+
+/// An opaque token for the page after the link `id`.
+pub fn encode_cursor(keys: &SigningKeys, id: i64) -> String {
+    let signature = keys.sign(&signed_message(id));
+    URL_SAFE_NO_PAD.encode(format!("{id}.{signature}"))
+}
+
+/// The id a cursor from `encode_cursor` continues after.
+pub fn decode_cursor(keys: &SigningKeys, cursor: &str) -> Result<i64> {
+    let invalid = || anyhow!("invalid cursor");
+    let decoded = URL_SAFE_NO_PAD.decode(cursor).map_err(|_| invalid())?;
+    let decoded = String::from_utf8(decoded).map_err(|_| invalid())?;
+    let (id, signature) = decoded.split_once('.').ok_or_else(invalid)?;
+    let id: i64 = id.parse().map_err(|_| invalid())?;
+    if !keys.verify(&signed_message(id), signature) {
+        return Err(invalid());
+    }
+    Ok(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn cursors_round_trip_and_reject_tampering() {
+        let keys = SigningKeys::new(&Config::from_env().unwrap());
+        let cursor = encode_cursor(&keys, 42);
+        assert_eq!(decode_cursor(&keys, &cursor).unwrap(), 42);
+
+        let decoded = String::from_utf8(URL_SAFE_NO_PAD.decode(&cursor).unwrap()).unwrap();
+        let (_, signature) = decoded.split_once('.').unwrap();
+        let forged = URL_SAFE_NO_PAD.encode(format!("43.{signature}"));
+        assert!(decode_cursor(&keys, &forged).is_err());
+        assert!(decode_cursor(&keys, "not a cursor").is_err());
+    }
+}
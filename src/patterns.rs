@@ -0,0 +1,238 @@
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex, PoisonError},
+};
+
+use anyhow::{anyhow, Result};
+use axum::{extract::State, http::StatusCode, Json};
+use regex::Regex;
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{config::Config, db, ItoError, ItoJsonError, ItoPool};
+
+/// A `{name}` placeholder in a link template's target.
+static PLACEHOLDER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\{(\w+)\}").expect("placeholder regex is valid"));
+
+/// Patterns and templates compiled so far, by their stored pattern. There are
+/// few and they are never edited, so nothing is evicted. Ones that don't
+/// compile are kept as `None` so they are only logged once.
+static COMPILED: LazyLock<Mutex<HashMap<String, Option<Regex>>>> = LazyLock::new(Default::default);
+
+/// Patterns must match the whole alias, so `jira-(\d+)` doesn't also match `xjira-1`.
+fn compile(alias_pattern: &str) -> Result<Regex, regex::Error> {
+    Regex::new(&format!("^(?:{alias_pattern})$"))
+}
+
+/// `pattern` compiled, or `None` if it doesn't compile. Rows stored before
+/// patterns were validated, or by hand, are skipped rather than failing
+/// every redirect.
+fn compiled(pattern: &str) -> Option<Regex> {
+    let mut compiled = COMPILED.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(regex) = compiled.get(pattern) {
+        return regex.clone();
+    }
+    let regex = compile(pattern)
+        .inspect_err(|err| tracing::warn!("skipping alias pattern {pattern:?}: {err}"))
+        .ok();
+    compiled.insert(pattern.to_string(), regex.clone());
+    regex
+}
+
+/// `target` as a URL, if it is one and its domain is allowed. Otherwise the
+/// pattern that produced it is skipped.
+fn permitted_target(config: &Config, pattern: &str, target: &str) -> Option<Url> {
+    let target_url = Url::parse(target)
+        .inspect_err(|err| {
+            tracing::warn!(
+                "skipping pattern {pattern:?}: it produced invalid URL {target:?}: {err}"
+            )
+        })
+        .ok()?;
+    if !config.target_domain_permitted(&target_url) {
+        tracing::warn!("skipping pattern {pattern:?}: links to {target_url} are not allowed");
+        return None;
+    }
+    Some(target_url)
+}
+
+/// Finds the highest priority pattern matching `alias` and returns its target
+/// with the capture groups substituted in.
+pub fn resolve(conn: &Connection, config: &Config, alias: &str) -> Result<Option<Url>> {
+    let mut statement = conn.prepare(
+        "SELECT alias_pattern, target_template FROM link_patterns ORDER BY priority DESC, id",
+    )?;
+    let mut rows = statement.query([])?;
+    while let Some(row) = rows.next()? {
+        let alias_pattern: String = row.get(0)?;
+        let target_template: String = row.get(1)?;
+        let Some(regex) = compiled(&alias_pattern) else {
+            continue;
+        };
+        let Some(captures) = regex.captures(alias) else {
+            continue;
+        };
+        let mut target = String::new();
+        captures.expand(&target_template, &mut target);
+        if let Some(target) = permitted_target(config, &alias_pattern, &target) {
+            return Ok(Some(target));
+        }
+    }
+    Ok(None)
+}
+
+#[derive(Deserialize)]
+pub struct CreatePatternInput {
+    alias_pattern: String,
+    target_template: String,
+    #[serde(default)]
+    priority: i64,
+}
+
+#[derive(Serialize)]
+pub struct Pattern {
+    id: i64,
+    alias_pattern: String,
+    target_template: String,
+    priority: i64,
+}
+
+/// Adds an alias pattern. Patterns are only consulted when no link has the exact alias.
+pub async fn create_pattern(
+    State(pool): State<ItoPool>,
+    Json(input): Json<CreatePatternInput>,
+) -> Result<(StatusCode, Json<Pattern>), ItoJsonError> {
+    compile(&input.alias_pattern).map_err(|err| ItoError {
+        err: err.into(),
+        sc: StatusCode::BAD_REQUEST,
+    })?;
+    let pattern = db::interact(&pool, move |conn| -> Result<_, ItoError> {
+        conn.execute(
+            "INSERT INTO link_patterns (alias_pattern, target_template, priority, created_at)
+            VALUES (?1, ?2, ?3, strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))",
+            params![input.alias_pattern, input.target_template, input.priority],
+        )?;
+        Ok(Pattern {
+            id: conn.last_insert_rowid(),
+            alias_pattern: input.alias_pattern,
+            target_template: input.target_template,
+            priority: input.priority,
+        })
+    })
+    .await?;
+    Ok((StatusCode::CREATED, Json(pattern)))
+}
+
+/// Finds the highest priority link template matching `alias` and returns its
+/// target with each `{name}` replaced by the named capture group.
+pub fn resolve_template(conn: &Connection, config: &Config, alias: &str) -> Result<Option<Url>> {
+    let mut statement = conn.prepare(
+        "SELECT pattern, target_template FROM link_templates ORDER BY priority DESC, id",
+    )?;
+    let mut rows = statement.query([])?;
+    while let Some(row) = rows.next()? {
+        let pattern: String = row.get(0)?;
+        let target_template: String = row.get(1)?;
+        let Some(regex) = compiled(&pattern) else {
+            continue;
+        };
+        let Some(captures) = regex.captures(alias) else {
+            continue;
+        };
+        let target = PLACEHOLDER.replace_all(&target_template, |placeholder: &regex::Captures| {
+            captures
+                .name(&placeholder[1])
+                .map_or("", |capture| capture.as_str())
+                .to_string()
+        });
+        if let Some(target) = permitted_target(config, &pattern, &target) {
+            return Ok(Some(target));
+        }
+    }
+    Ok(None)
+}
+
+#[derive(Deserialize)]
+pub struct CreateTemplateInput {
+    pattern: String,
+    target_template: String,
+    #[serde(default)]
+    priority: i64,
+}
+
+#[derive(Serialize)]
+pub struct LinkTemplate {
+    id: i64,
+    pattern: String,
+    target_template: String,
+    priority: i64,
+}
+
+/// Adds a link template, like `docs-v(?P<version>\d+)` expanding to
+/// `https://docs.example.com/v{version}/`. Templates are consulted after
+/// alias patterns.
+pub async fn create_template(
+    State(pool): State<ItoPool>,
+    Json(input): Json<CreateTemplateInput>,
+) -> Result<(StatusCode, Json<LinkTemplate>), ItoJsonError> {
+    let bad_request = |err| ItoError {
+        err,
+        sc: StatusCode::BAD_REQUEST,
+    };
+    let pattern = compile(&input.pattern).map_err(|err| bad_request(err.into()))?;
+    for placeholder in PLACEHOLDER.captures_iter(&input.target_template) {
+        let name = &placeholder[1];
+        if !pattern.capture_names().any(|group| group == Some(name)) {
+            return Err(
+                bad_request(anyhow!("the pattern has no capture group named {name}")).into(),
+            );
+        }
+    }
+    let template = db::interact(&pool, move |conn| -> Result<_, ItoError> {
+        conn.execute(
+            "INSERT INTO link_templates (pattern, target_template, priority) VALUES (?1, ?2, ?3)",
+            params![input.pattern, input.target_template, input.priority],
+        )?;
+        Ok(LinkTemplate {
+            id: conn.last_insert_rowid(),
+            pattern: input.pattern,
+            target_template: input.target_template,
+            priority: input.priority,
+        })
+    })
+    .await?;
+    Ok((StatusCode::CREATED, Json(template)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_skips_bad_patterns_and_blocked_targets() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        db::migrate(&mut conn).unwrap();
+        let mut config = Config::from_env().unwrap();
+        config.blocked_target_domains = vec!["blocked.example.com".to_string()];
+        for (alias_pattern, target_template, priority) in [
+            ("bad-(", "https://example.com/", 3),
+            (r"t-(\d+)", "https://blocked.example.com/$1", 2),
+            (r"t-(\d+)", "not a url $1", 1),
+            (r"t-(\d+)", "https://example.com/t/$1", 0),
+        ] {
+            conn.execute(
+                "INSERT INTO link_patterns (alias_pattern, target_template, priority, created_at)
+                VALUES (?1, ?2, ?3, '2024-01-01T00:00:00Z')",
+                params![alias_pattern, target_template, priority],
+            )
+            .unwrap();
+        }
+        assert_eq!(
+            resolve(&conn, &config, "t-7").unwrap().unwrap().as_str(),
+            "https://example.com/t/7"
+        );
+        assert!(resolve(&conn, &config, "x").unwrap().is_none());
+    }
+}
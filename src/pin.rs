@@ -0,0 +1,94 @@
+use std::time::Duration;
+
+use anyhow::anyhow;
+use askama::Template;
+use axum::http::{header, HeaderMap};
+use chrono::Utc;
+
+use crate::{config::Config, keys::SigningKeys, ItoError};
+
+/// How long a correct PIN is remembered before it has to be entered again.
+const UNLOCK_LIFETIME: Duration = Duration::from_secs(60 * 60);
+
+/// Asks for the PIN of a link protected by `access_password_hash`.
+#[derive(Template)]
+#[template(path = "pin.html")]
+pub struct PinTemplate {
+    pub alias: String,
+    /// Whether a wrong PIN was just entered.
+    pub wrong: bool,
+}
+
+fn cookie_name(link_id: i64) -> String {
+    format!("ito_pin_{link_id}")
+}
+
+/// Signs the hash along with the expiry, so changing a link's PIN locks out
+/// everyone who entered the old one.
+fn signed_message(link_id: i64, expires_at: i64, password_hash: &str) -> String {
+    format!("pin:{link_id}:{expires_at}:{password_hash}")
+}
+
+/// `Set-Cookie` for a browser that just entered the PIN of link `link_id`.
+pub fn unlock_cookie(
+    config: &Config,
+    keys: &SigningKeys,
+    link_id: i64,
+    password_hash: &str,
+) -> String {
+    let expires_at = Utc::now().timestamp() + UNLOCK_LIFETIME.as_secs() as i64;
+    let signature = keys.sign(&signed_message(link_id, expires_at, password_hash));
+    let secure = if config.base_url.scheme() == "https" {
+        "; Secure"
+    } else {
+        ""
+    };
+    format!(
+        "{}={expires_at}.{signature}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax{secure}",
+        cookie_name(link_id),
+        UNLOCK_LIFETIME.as_secs(),
+    )
+}
+
+/// Whether the request carries an unexpired cookie from `unlock_cookie`.
+pub fn is_unlocked(
+    keys: &SigningKeys,
+    headers: &HeaderMap,
+    link_id: i64,
+    password_hash: &str,
+) -> bool {
+    let name = cookie_name(link_id);
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .filter_map(|cookie| cookie.trim().strip_prefix(name.as_str())?.strip_prefix('='))
+        .filter_map(|value| value.split_once('.'))
+        .any(|(expires_at, signature)| {
+            expires_at.parse().is_ok_and(|expires_at: i64| {
+                expires_at > Utc::now().timestamp()
+                    && keys.verify(
+                        &signed_message(link_id, expires_at, password_hash),
+                        signature,
+                    )
+            })
+        })
+}
+
+/// Hashes a PIN for `links.access_password_hash`. Hashing is slow on
+/// purpose, so it and `verify` happen off the async runtime.
+pub async fn hash(pin: String) -> Result<String, ItoError> {
+    tokio::task::spawn_blocking(move || bcrypt::hash(pin, bcrypt::DEFAULT_COST))
+        .await
+        .map_err(|err| anyhow!("failed to hash PIN: {err}"))?
+        .map_err(ItoError::from)
+}
+
+/// Whether `pin` matches `password_hash`.
+pub async fn verify(pin: String, password_hash: String) -> Result<bool, ItoError> {
+    tokio::task::spawn_blocking(move || bcrypt::verify(pin, &password_hash))
+        .await
+        .map_err(|err| anyhow!("failed to check PIN: {err}"))?
+        .map_err(ItoError::from)
+}
@@ -0,0 +1,111 @@
+use axum::{extract::State, Json};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use crate::{db, users::User, ItoError, ItoJsonError, ItoPool, ReadPool};
+
+/// A part of each link the root page can show.
+#[derive(Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkColumn {
+    Alias,
+    TargetUrl,
+    ClickCount,
+    CreatedAt,
+    ExpiresAt,
+    Tags,
+    Description,
+    LastChecked,
+}
+
+const ALL_COLUMNS: [LinkColumn; 8] = [
+    LinkColumn::Alias,
+    LinkColumn::TargetUrl,
+    LinkColumn::ClickCount,
+    LinkColumn::CreatedAt,
+    LinkColumn::ExpiresAt,
+    LinkColumn::Tags,
+    LinkColumn::Description,
+    LinkColumn::LastChecked,
+];
+
+/// Which `LinkColumn`s the root page shows, for the template to check.
+pub struct ShownColumns {
+    pub alias: bool,
+    pub target_url: bool,
+    pub click_count: bool,
+    pub created_at: bool,
+    pub expires_at: bool,
+    pub tags: bool,
+    pub description: bool,
+    pub last_checked: bool,
+}
+
+impl ShownColumns {
+    fn new(columns: &[LinkColumn]) -> Self {
+        let shows = |column| columns.contains(&column);
+        Self {
+            alias: shows(LinkColumn::Alias),
+            target_url: shows(LinkColumn::TargetUrl),
+            click_count: shows(LinkColumn::ClickCount),
+            created_at: shows(LinkColumn::CreatedAt),
+            expires_at: shows(LinkColumn::ExpiresAt),
+            tags: shows(LinkColumn::Tags),
+            description: shows(LinkColumn::Description),
+            last_checked: shows(LinkColumn::LastChecked),
+        }
+    }
+}
+
+/// The columns `user_id` chose, or every column if they haven't chosen.
+pub fn link_columns(conn: &Connection, user_id: i64) -> Result<Vec<LinkColumn>, ItoError> {
+    let columns: Option<String> = conn
+        .query_row(
+            "SELECT link_columns FROM users WHERE id = ?",
+            [user_id],
+            |row| row.get(0),
+        )
+        .optional()?
+        .flatten();
+    Ok(match columns {
+        Some(columns) => serde_json::from_str(&columns)?,
+        None => ALL_COLUMNS.to_vec(),
+    })
+}
+
+pub fn shown_columns(conn: &Connection, user_id: i64) -> Result<ShownColumns, ItoError> {
+    Ok(ShownColumns::new(&link_columns(conn, user_id)?))
+}
+
+/// The columns the root page shows `user`, in the order they chose them.
+pub async fn get_columns(
+    State(ReadPool(pool)): State<ReadPool>,
+    user: User,
+) -> Result<Json<Vec<LinkColumn>>, ItoJsonError> {
+    let columns = db::interact(&pool, move |conn| link_columns(conn, user.id)).await?;
+    Ok(Json(columns))
+}
+
+/// Saves which columns the root page shows `user`.
+pub async fn set_columns(
+    State(pool): State<ItoPool>,
+    user: User,
+    Json(mut columns): Json<Vec<LinkColumn>>,
+) -> Result<Json<Vec<LinkColumn>>, ItoJsonError> {
+    let mut seen = Vec::new();
+    columns.retain(|column| {
+        let first = !seen.contains(column);
+        seen.push(*column);
+        first
+    });
+    let stored = serde_json::to_string(&columns)?;
+    db::interact(&pool, move |conn| {
+        conn.execute(
+            "UPDATE users SET link_columns = ?1 WHERE id = ?2",
+            params![stored, user.id],
+        )
+        .map_err(ItoError::from)
+    })
+    .await?;
+    Ok(Json(columns))
+}
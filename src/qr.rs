@@ -0,0 +1,48 @@
+use std::io::{Cursor, Write};
+
+use anyhow::Result;
+use image::{DynamicImage, ImageFormat, Luma};
+use qrcode::QrCode;
+use zip::{
+    write::{SimpleFileOptions, StreamWriter},
+    CompressionMethod, ZipWriter,
+};
+
+/// Encodes `data` as a QR code PNG.
+pub fn png(data: &str) -> Result<Vec<u8>> {
+    let image = QrCode::new(data)?.render::<Luma<u8>>().build();
+    let mut png = Cursor::new(Vec::new());
+    DynamicImage::ImageLuma8(image).write_to(&mut png, ImageFormat::Png)?;
+    Ok(png.into_inner())
+}
+
+/// Encodes `data` as a QR code SVG, for embedding in a page.
+pub fn svg(data: &str) -> Result<String> {
+    Ok(QrCode::new(data)?
+        .render::<qrcode::render::svg::Color>()
+        .min_dimensions(200, 200)
+        .build())
+}
+
+/// A ZIP archive written to `out` as files are added, without seeking back,
+/// so it can be streamed.
+pub struct ZipStream<W: Write>(ZipWriter<StreamWriter<W>>);
+
+impl<W: Write> ZipStream<W> {
+    pub fn new(out: W) -> Self {
+        Self(ZipWriter::new_stream(out))
+    }
+
+    pub fn add(&mut self, name: String, contents: &[u8]) -> Result<()> {
+        // PNGs are already compressed, so there's nothing to gain from deflating.
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        self.0.start_file(name, options)?;
+        self.0.write_all(contents)?;
+        Ok(())
+    }
+
+    /// Writes the archive's central directory, returning `out`.
+    pub fn finish(self) -> Result<W> {
+        Ok(self.0.finish()?.into_inner())
+    }
+}
@@ -0,0 +1,173 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{header, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tower_sessions::Session;
+
+use crate::{auth, config::Config, users, ItoError};
+
+const WINDOW: Duration = Duration::from_secs(60);
+const REDIRECT_WINDOW: Duration = Duration::from_secs(60 * 60);
+const PIN_WINDOW: Duration = Duration::from_secs(15 * 60);
+/// Enough for typos, too few to guess a short PIN.
+const MAX_PIN_ATTEMPTS: u64 = 10;
+
+/// Past this many tracked clients, windows that have ended are forgotten.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Counts requests per client in fixed windows, one minute long by default.
+pub struct RateLimiter {
+    window: Duration,
+    windows: Mutex<HashMap<String, (Instant, u64)>>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(WINDOW)
+    }
+}
+
+impl RateLimiter {
+    fn new(window: Duration) -> Self {
+        Self {
+            window,
+            windows: Mutex::default(),
+        }
+    }
+
+    /// Counts a request from `key`. When `key` is already at `limit` requests
+    /// this window, returns how long until the next window starts instead.
+    fn check(&self, key: String, limit: u64) -> Result<(), Duration> {
+        let now = Instant::now();
+        let window = self.window;
+        let mut windows = self.windows.lock().unwrap_or_else(PoisonError::into_inner);
+        if windows.len() > PRUNE_THRESHOLD {
+            windows.retain(|_, (started, _)| now.duration_since(*started) < window);
+        }
+        let (started, count) = windows.entry(key).or_insert((now, 0));
+        if now.duration_since(*started) >= window {
+            (*started, *count) = (now, 0);
+        }
+        if *count >= limit {
+            return Err(window - now.duration_since(*started));
+        }
+        *count += 1;
+        Ok(())
+    }
+
+    fn forget(&self, key: &str) {
+        let mut windows = self.windows.lock().unwrap_or_else(PoisonError::into_inner);
+        windows.remove(key);
+    }
+}
+
+/// Counts redirects per alias in hour long windows, for
+/// `Config::max_redirects_per_alias_per_hour`. Counts are only kept in
+/// memory, so a restart starts every alias afresh.
+pub struct RedirectLimiter(RateLimiter);
+
+impl Default for RedirectLimiter {
+    fn default() -> Self {
+        Self(RateLimiter::new(REDIRECT_WINDOW))
+    }
+}
+
+impl RedirectLimiter {
+    /// Counts a redirect for `alias`. When it has already had `limit` this
+    /// hour, returns the 429 response to send instead.
+    pub fn check(&self, alias: &str, limit: u64) -> Option<Response> {
+        let retry_after = self.0.check(alias.to_lowercase(), limit).err()?;
+        Some(too_many_requests(
+            retry_after,
+            format!("{alias} has had too many visits, try again later"),
+        ))
+    }
+
+    /// Starts `alias` afresh, for when its link is deleted.
+    pub fn forget(&self, alias: &str) {
+        self.0.forget(&alias.to_lowercase());
+    }
+}
+
+/// Counts PIN entries per alias in 15 minute windows. Counting per alias
+/// rather than per client means spreading guesses over many addresses
+/// doesn't help, at the cost of locking out the PIN form for everyone.
+pub struct PinAttempts(RateLimiter);
+
+impl Default for PinAttempts {
+    fn default() -> Self {
+        Self(RateLimiter::new(PIN_WINDOW))
+    }
+}
+
+impl PinAttempts {
+    /// Counts an attempt at `alias`'s PIN, returning the 429 response to
+    /// send instead once there have been too many.
+    pub fn check(&self, alias: &str) -> Option<Response> {
+        let retry_after = self.0.check(alias.to_lowercase(), MAX_PIN_ATTEMPTS).err()?;
+        Some(too_many_requests(
+            retry_after,
+            format!("too many PINs entered for {alias}, try again later"),
+        ))
+    }
+}
+
+fn too_many_requests(retry_after: Duration, message: String) -> Response {
+    (
+        [(
+            header::RETRY_AFTER,
+            retry_after.as_secs().max(1).to_string(),
+        )],
+        ItoError {
+            err: anyhow!(message),
+            sc: StatusCode::TOO_MANY_REQUESTS,
+        },
+    )
+        .into_response()
+}
+
+/// Limits link creation per signed-in user or bearer token, and per IP
+/// address for everyone else, so clients behind a shared NAT address don't
+/// use up each other's allowance.
+pub async fn limit_link_creation<B>(
+    State(config): State<Arc<Config>>,
+    State(limiter): State<Arc<RateLimiter>>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let user_key = match auth::token_subject(&config, req.headers()) {
+        Some(sub) => Some(format!("token:{sub}")),
+        None => req
+            .extensions()
+            .get::<Session>()
+            .and_then(users::session_user_id)
+            .map(|user_id| format!("user:{user_id}")),
+    };
+    let (key, limit) = match user_key {
+        Some(key) => (key, config.create_rate_limit_per_user),
+        None => match connect_info {
+            Some(ConnectInfo(addr)) => {
+                (format!("ip:{}", addr.ip()), config.create_rate_limit_per_ip)
+            }
+            None => return next.run(req).await,
+        },
+    };
+    match limiter.check(key, limit.into()) {
+        Ok(()) => next.run(req).await,
+        Err(retry_after) => too_many_requests(
+            retry_after,
+            "too many links created, try again later".to_string(),
+        ),
+    }
+}
@@ -0,0 +1,96 @@
+use anyhow::Result;
+use askama::Template;
+use rusqlite::Connection;
+use url::Url;
+
+use crate::config::Config;
+
+/// The heading links without a tag are listed under.
+const UNTAGGED: &str = "Untagged";
+
+/// A Markdown directory of links, by tag. `templates/readme.md` can be
+/// edited to change its layout; it isn't HTML-escaped.
+#[derive(Template)]
+#[template(path = "readme.md", escape = "none")]
+struct ReadmeTemplate {
+    sections: Vec<Section>,
+}
+
+struct Section {
+    name: String,
+    /// The fragment Markdown renderers give the section's heading.
+    anchor: String,
+    links: Vec<ReadmeLink>,
+}
+
+/// A link, with each field ready to go in a Markdown table cell.
+struct ReadmeLink {
+    alias: String,
+    short_url: Url,
+    target_url: String,
+    description: String,
+    created_at: String,
+}
+
+/// Renders every unexpired link, under a heading for each of its tags, with
+/// tags and aliases in alphabetical order and untagged links last.
+pub fn render(conn: &Connection, config: &Config) -> Result<String> {
+    let mut statement = conn.prepare(
+        "SELECT tags.name, links.alias, links.target_url, links.description, links.created_at
+        FROM links
+        LEFT JOIN link_tags ON link_tags.link_id = links.id
+        LEFT JOIN tags ON tags.id = link_tags.tag_id
+        WHERE links.expires_at IS NULL
+            OR links.expires_at > strftime('%Y-%m-%dT%H:%M:%SZ', 'now')
+        ORDER BY tags.name IS NULL, tags.name COLLATE NOCASE, links.alias COLLATE NOCASE",
+    )?;
+    let mut rows = statement.query([])?;
+    let mut sections: Vec<Section> = Vec::new();
+    while let Some(row) = rows.next()? {
+        let name = row
+            .get::<_, Option<String>>(0)?
+            .unwrap_or_else(|| UNTAGGED.to_string());
+        let alias: String = row.get(1)?;
+        let link = ReadmeLink {
+            short_url: config.short_url(&alias)?,
+            alias: table_cell(&alias),
+            target_url: table_cell(&row.get::<_, String>(2)?),
+            description: table_cell(&row.get::<_, Option<String>>(3)?.unwrap_or_default()),
+            created_at: row
+                .get::<_, Option<String>>(4)?
+                .map(|created_at| config.timestamp_precision.truncate(created_at))
+                .unwrap_or_default(),
+        };
+        match sections.last_mut() {
+            Some(section) if section.name == name => section.links.push(link),
+            _ => sections.push(Section {
+                anchor: anchor(&name),
+                name,
+                links: vec![link],
+            }),
+        }
+    }
+    Ok(ReadmeTemplate { sections }.render()?)
+}
+
+/// `text` on one line, with the pipes that would end a table cell escaped.
+fn table_cell(text: &str) -> String {
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .replace('|', "\\|")
+}
+
+/// A heading's fragment as GitHub makes it: lowercase, punctuation dropped
+/// and spaces turned into hyphens.
+fn anchor(heading: &str) -> String {
+    heading
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            c if c.is_alphanumeric() || c == '-' || c == '_' => Some(c),
+            _ => None,
+        })
+        .collect()
+}
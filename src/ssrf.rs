@@ -0,0 +1,165 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{bail, Result};
+use reqwest::{
+    dns::{Addrs, Name, Resolve, Resolving},
+    redirect::Policy,
+};
+use url::{Host, Url};
+
+use crate::config::Config;
+
+/// Redirects followed before giving up, as many as reqwest follows by default.
+const MAX_REDIRECTS: usize = 10;
+
+/// A client for fetching pages at URLs that users give, which only connects
+/// to public addresses, so a link can't be used to reach ito's own network.
+/// Names are checked as they are resolved, for every redirect too, so a name
+/// can't resolve to a public address when checked and a private one when
+/// connected to. Requests through a configured proxy are resolved by the
+/// proxy.
+#[derive(Clone)]
+pub struct PublicClient(reqwest::Client);
+
+impl PublicClient {
+    pub fn new(config: &Config, timeout: Duration) -> Result<Self> {
+        let client = config
+            .http_client_builder(timeout)?
+            .dns_resolver(Arc::new(PublicResolver))
+            .redirect(Policy::custom(|attempt| {
+                if attempt.previous().len() >= MAX_REDIRECTS {
+                    attempt.error("too many redirects")
+                } else if let Err(err) = check_host(attempt.url()) {
+                    attempt.error(err.to_string())
+                } else {
+                    attempt.follow()
+                }
+            }))
+            .build()?;
+        Ok(Self(client))
+    }
+
+    /// A GET request for `url`, unless its host is a private address.
+    pub fn get(&self, url: &Url) -> Result<reqwest::RequestBuilder> {
+        check_host(url)?;
+        Ok(self.0.get(url.clone()))
+    }
+}
+
+/// Rejects URLs whose host is a non-public IP address. Names are left to
+/// `PublicResolver`, which doesn't see IP addresses.
+fn check_host(url: &Url) -> Result<()> {
+    let ip = match url.host() {
+        Some(Host::Ipv4(ip)) => IpAddr::V4(ip),
+        Some(Host::Ipv6(ip)) => IpAddr::V6(ip),
+        Some(Host::Domain(_)) => return Ok(()),
+        None => bail!("{url} has no host"),
+    };
+    if !is_public(ip) {
+        bail!("{ip} is not a public address");
+    }
+    Ok(())
+}
+
+/// Resolves names to their public addresses only.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address", name.as_str()).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Whether `ip` is reachable on the internet, rather than loopback, private,
+/// link-local or otherwise reserved.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => is_public_v6(ip),
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // "This network", shared address space, IETF protocol assignments,
+        // benchmarking and reserved.
+        || a == 0
+        || (a == 100 && (64..128).contains(&b))
+        || (a == 192 && b == 0 && ip.octets()[2] == 0)
+        || (a == 198 && (18..20).contains(&b))
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    if let Some(ip) = ip.to_ipv4_mapped() {
+        return is_public_v4(ip);
+    }
+    let segments = ip.segments();
+    // NAT64 addresses reach the IPv4 address in their last 32 bits.
+    if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        let [.., high, low] = segments;
+        return is_public_v4(Ipv4Addr::from((u32::from(high) << 16) | u32::from(low)));
+    }
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // Unique local, link-local and documentation.
+        || (segments[0] & 0xfe00) == 0xfc00
+        || (segments[0] & 0xffc0) == 0xfe80
+        || (segments[0] == 0x2001 && segments[1] == 0xdb8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_public_addresses_are_public() {
+        for ip in ["93.184.216.34", "2606:2800:220:1:248:1893:25c8:1946"] {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "64:ff9b::a9fe:a9fe",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[test]
+    fn check_host_rejects_private_ip_literals() {
+        assert!(check_host(&"http://169.254.169.254/latest".parse().unwrap()).is_err());
+        assert!(check_host(&"http://[::1]:8080/".parse().unwrap()).is_err());
+        assert!(check_host(&"https://example.com/".parse().unwrap()).is_ok());
+    }
+}
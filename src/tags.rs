@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+
+use axum::{extract::State, http::StatusCode, Json};
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+
+use crate::{db, handle_sqlite_err, ItoError, ItoJsonError, ItoPool, ReadPool};
+
+#[derive(Deserialize)]
+pub struct CreateTagInput {
+    name: String,
+    parent_id: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct Tag {
+    id: i64,
+    name: String,
+    parent_id: Option<i64>,
+}
+
+/// Adds a tag, optionally under `parent_id`. Tags otherwise come into being,
+/// without a parent, when a link is first given them.
+pub async fn create_tag(
+    State(pool): State<ItoPool>,
+    Json(input): Json<CreateTagInput>,
+) -> Result<(StatusCode, Json<Tag>), ItoJsonError> {
+    let tag = db::interact(&pool, move |conn| -> Result<_, ItoError> {
+        conn.execute(
+            "INSERT INTO tags (name, parent_id) VALUES (?1, ?2)",
+            params![input.name, input.parent_id],
+        )
+        .map_err(handle_sqlite_err)?;
+        Ok(Tag {
+            id: conn.last_insert_rowid(),
+            name: input.name,
+            parent_id: input.parent_id,
+        })
+    })
+    .await?;
+    Ok((StatusCode::CREATED, Json(tag)))
+}
+
+#[derive(Serialize)]
+pub struct TagTree {
+    id: i64,
+    name: String,
+    children: Vec<TagTree>,
+}
+
+/// Every tag, nested under its parent.
+pub async fn list_tags(
+    State(ReadPool(pool)): State<ReadPool>,
+) -> Result<Json<Vec<TagTree>>, ItoJsonError> {
+    let tags = db::interact(&pool, |conn| -> Result<_, ItoError> {
+        let mut statement = conn.prepare("SELECT id, name, parent_id FROM tags ORDER BY name")?;
+        let rows = statement.query_map([], |row| {
+            Ok(Tag {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                parent_id: row.get(2)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    })
+    .await?;
+    let mut children: HashMap<Option<i64>, Vec<Tag>> = HashMap::new();
+    for tag in tags {
+        children.entry(tag.parent_id).or_default().push(tag);
+    }
+    Ok(Json(subtree(&mut children, None)))
+}
+
+/// Builds the trees under `parent_id`. A parent can only be given when a tag
+/// is created, so there are no cycles to guard against.
+fn subtree(children: &mut HashMap<Option<i64>, Vec<Tag>>, parent_id: Option<i64>) -> Vec<TagTree> {
+    children
+        .remove(&parent_id)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|tag| TagTree {
+            id: tag.id,
+            children: subtree(children, Some(tag.id)),
+            name: tag.name,
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+pub struct BulkTagInput {
+    link_ids: Vec<i64>,
+    #[serde(default)]
+    add_tags: Vec<String>,
+    #[serde(default)]
+    remove_tags: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct BulkTagReport {
+    /// Links whose tags changed.
+    modified_count: usize,
+    not_found_ids: Vec<i64>,
+}
+
+/// Adds and removes tags on many links at once, in a single transaction.
+pub async fn bulk_tag(
+    State(pool): State<ItoPool>,
+    Json(input): Json<BulkTagInput>,
+) -> Result<Json<BulkTagReport>, ItoJsonError> {
+    let report = db::interact(&pool, move |conn| -> Result<_, ItoError> {
+        let tx = conn.transaction()?;
+        let add_tags: Vec<&str> = input
+            .add_tags
+            .iter()
+            .map(|tag| tag.trim())
+            .filter(|tag| !tag.is_empty())
+            .collect();
+        for tag in &add_tags {
+            tx.execute("INSERT OR IGNORE INTO tags (name) VALUES (?)", [tag])?;
+        }
+        let mut report = BulkTagReport {
+            modified_count: 0,
+            not_found_ids: Vec::new(),
+        };
+        for link_id in input.link_ids {
+            let exists: bool = tx.query_row(
+                "SELECT EXISTS (SELECT 1 FROM links WHERE id = ?)",
+                [link_id],
+                |row| row.get(0),
+            )?;
+            if !exists {
+                report.not_found_ids.push(link_id);
+                continue;
+            }
+            let mut changes = 0;
+            for tag in &add_tags {
+                changes += tx.execute(
+                    "INSERT OR IGNORE INTO link_tags (link_id, tag_id)
+                    SELECT ?1, id FROM tags WHERE name = ?2",
+                    params![link_id, tag],
+                )?;
+            }
+            for tag in &input.remove_tags {
+                changes += tx.execute(
+                    "DELETE FROM link_tags
+                    WHERE link_id = ?1 AND tag_id IN (SELECT id FROM tags WHERE name = ?2)",
+                    params![link_id, tag.trim()],
+                )?;
+            }
+            if changes > 0 {
+                report.modified_count += 1;
+            }
+        }
+        tx.commit()?;
+        Ok(report)
+    })
+    .await?;
+    Ok(Json(report))
+}
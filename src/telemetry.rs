@@ -0,0 +1,79 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use axum::{
+    extract::State,
+    http::{HeaderMap, Request},
+    middleware::Next,
+    response::Response,
+};
+use opentelemetry::{global, propagation::Extractor, trace::TracerProvider, KeyValue};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::SdkTracerProvider, Resource};
+use tracing::{Instrument, Subscriber};
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::registry::LookupSpan;
+use url::Url;
+
+use crate::config::Config;
+
+/// A layer that sends spans to the OTLP/HTTP collector at `endpoint`, for
+/// `Config::otel_endpoint`.
+pub fn layer<S>(endpoint: &Url) -> Result<OpenTelemetryLayer<S, opentelemetry_sdk::trace::Tracer>>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint.as_str())
+        .build()
+        .context("failed to set up the OpenTelemetry exporter")?;
+    let resource = Resource::builder()
+        .with_service_name("ito")
+        .with_attribute(KeyValue::new("service.version", env!("CARGO_PKG_VERSION")))
+        .build();
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource)
+        .build();
+    let tracer = provider.tracer("ito");
+    global::set_tracer_provider(provider);
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    Ok(tracing_opentelemetry::layer().with_tracer(tracer))
+}
+
+/// Reads propagated context out of request headers.
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+/// Runs each request in a span that continues the trace in its
+/// `traceparent` header, when traces are exported at all.
+pub async fn trace_request<B>(
+    State(config): State<Arc<Config>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if config.otel_endpoint.is_none() {
+        return next.run(req).await;
+    }
+    let parent = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(req.headers()))
+    });
+    let span = tracing::info_span!(
+        "request",
+        method = %req.method(),
+        path = req.uri().path(),
+    );
+    // Only fails when no OpenTelemetry layer is installed.
+    let _ = span.set_parent(parent);
+    next.run(req).instrument(span).await
+}
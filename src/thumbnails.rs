@@ -0,0 +1,157 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rusqlite::params;
+use url::Url;
+
+use crate::{config::Config, db, handle_sqlite_err, users::User, ItoError, ItoPool, ReadPool};
+
+/// How thumbnails were stored before `links.thumbnail_png`.
+const LEGACY_DATA_URL_PREFIX: &str = "data:image/png;base64,";
+
+/// Rendering a page in a headless browser can take a while.
+const SCREENSHOT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Screenshots larger than this are not kept.
+const MAX_SCREENSHOT_BYTES: usize = 2 * 1024 * 1024;
+
+/// Takes screenshots of link targets through a remote screenshot service,
+/// for `Config::screenshot_api_key`.
+pub struct Thumbnails {
+    client: reqwest::Client,
+    api_url: Url,
+    api_key: String,
+}
+
+impl Thumbnails {
+    /// `None` unless a screenshot API key is configured.
+    pub fn from_config(config: &Config) -> Result<Option<Self>> {
+        let Some(api_key) = &config.screenshot_api_key else {
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            client: config.http_client(SCREENSHOT_TIMEOUT)?,
+            api_url: config.screenshot_api_url.clone(),
+            api_key: api_key.clone(),
+        }))
+    }
+
+    /// Takes a screenshot of `target_url` in the background and stores it as
+    /// link `link_id`'s thumbnail. Failures are only logged, since a link
+    /// works just as well without one.
+    pub fn capture(&self, pool: ItoPool, link_id: i64, target_url: Url) {
+        let client = self.client.clone();
+        let request = self.request_url(&target_url);
+        tokio::spawn(async move {
+            if let Err(err) = capture(&pool, &client, request, link_id).await {
+                tracing::warn!("failed to take a thumbnail of {target_url}: {err:#}");
+            }
+        });
+    }
+
+    fn request_url(&self, target_url: &Url) -> Url {
+        let mut url = self.api_url.clone();
+        url.query_pairs_mut()
+            .append_pair("access_key", &self.api_key)
+            .append_pair("url", target_url.as_str())
+            .append_pair("format", "png")
+            .append_pair("viewport_width", "1280")
+            .append_pair("viewport_height", "800");
+        url
+    }
+}
+
+/// Stores the screenshot itself, so the API key in the request never reaches
+/// a browser and thumbnails keep working if the service's copy goes away.
+async fn capture(
+    pool: &ItoPool,
+    client: &reqwest::Client,
+    request: Url,
+    link_id: i64,
+) -> Result<()> {
+    let mut response = client
+        .get(request)
+        .send()
+        .await
+        // The request URL holds the API key.
+        .map_err(reqwest::Error::without_url)?
+        .error_for_status()
+        .map_err(reqwest::Error::without_url)?;
+    let is_png = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("image/png"));
+    if !is_png {
+        bail!("the screenshot service didn't return a PNG");
+    }
+    let mut png = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(reqwest::Error::without_url)?
+    {
+        png.extend_from_slice(&chunk);
+        if png.len() > MAX_SCREENSHOT_BYTES {
+            bail!("the screenshot is larger than {MAX_SCREENSHOT_BYTES} bytes");
+        }
+    }
+    db::interact(pool, move |conn| {
+        conn.execute(
+            "UPDATE links SET thumbnail_png = ?1, thumbnail_url = NULL WHERE id = ?2",
+            params![png, link_id],
+        )
+        .context("failed to store thumbnail")
+    })
+    .await?;
+    Ok(())
+}
+
+/// Serves link `link_id`'s thumbnail to users who can see the link, so the
+/// dashboard can load thumbnails lazily instead of inlining them.
+pub async fn serve(
+    State(ReadPool(pool)): State<ReadPool>,
+    user: User,
+    Path(link_id): Path<i64>,
+) -> Result<impl IntoResponse, ItoError> {
+    let (owner_id, png, legacy_url): (Option<i64>, Option<Vec<u8>>, Option<String>) =
+        db::interact(&pool, move |conn| {
+            conn.query_row(
+                "SELECT user_id, thumbnail_png, thumbnail_url FROM links WHERE id = ?",
+                [link_id],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .map_err(handle_sqlite_err)
+        })
+        .await?;
+    if !user.can_modify(owner_id) {
+        return Err(ItoError {
+            err: anyhow!("link {link_id} belongs to another user"),
+            sc: StatusCode::FORBIDDEN,
+        });
+    }
+    let legacy_png = || {
+        let data = legacy_url?
+            .strip_prefix(LEGACY_DATA_URL_PREFIX)?
+            .to_string();
+        STANDARD.decode(data).ok()
+    };
+    let Some(png) = png.or_else(legacy_png) else {
+        return Err(ItoError {
+            err: anyhow!("link {link_id} has no thumbnail"),
+            sc: StatusCode::NOT_FOUND,
+        });
+    };
+    Ok((
+        [
+            (header::CONTENT_TYPE, "image/png"),
+            (header::CACHE_CONTROL, "private, max-age=3600"),
+        ],
+        png,
+    ))
+}
@@ -0,0 +1,45 @@
+use std::{
+    env,
+    fs::{DirBuilder, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use rcgen::{Certificate, CertificateParams};
+use time::{Duration, OffsetDateTime};
+
+/// Generates a self-signed certificate for `localhost`, valid for one day,
+/// and writes it and its key to a new directory in the temp dir that only
+/// the current user can read. Returns `(cert_path, key_path)`.
+pub fn generate_dev_certificate() -> Result<(PathBuf, PathBuf)> {
+    let mut params = CertificateParams::new(vec!["localhost".to_string()]);
+    let now = OffsetDateTime::now_utc();
+    params.not_before = now;
+    params.not_after = now + Duration::days(1);
+    let cert = Certificate::from_params(params)?;
+
+    let mut suffix = [0; 8];
+    getrandom::fill(&mut suffix).map_err(|err| anyhow!("failed to name cert dir: {err}"))?;
+    let suffix: String = suffix.iter().map(|byte| format!("{byte:02x}")).collect();
+    let dir = env::temp_dir().join(format!("ito-dev-tls-{suffix}"));
+    let mut builder = DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    // Fails if the directory exists, so nobody else can have put files in it.
+    builder.create(&dir)?;
+    let cert_path = dir.join("cert.pem");
+    let key_path = dir.join("key.pem");
+    write_private(&cert_path, &cert.serialize_pem()?)?;
+    write_private(&key_path, &cert.serialize_private_key_pem())?;
+    Ok((cert_path, key_path))
+}
+
+fn write_private(path: &Path, contents: &str) -> Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(contents.as_bytes())?;
+    Ok(())
+}
@@ -0,0 +1,329 @@
+use anyhow::anyhow;
+use askama::Template;
+use axum::{
+    extract::{Form, State},
+    http::StatusCode,
+    response::{IntoResponse, Redirect, Response},
+};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rusqlite::{params, Connection};
+use serde::Deserialize;
+use totp_rs::{Algorithm, Secret, TOTP};
+use tower_sessions::Session;
+
+use crate::{
+    auth,
+    csrf::CsrfToken,
+    db, qr,
+    users::{self, User},
+    HtmlTemplate, ItoError, ItoPool,
+};
+
+/// The user who has given their password and still has to give a code.
+const PENDING_USER_ID_KEY: &str = "totp_pending_user_id";
+const FAILED_ATTEMPTS_KEY: &str = "totp_failed_attempts";
+/// The secret being enrolled, kept here until a code from it is confirmed.
+const ENROLLMENT_SECRET_KEY: &str = "totp_enrollment_secret";
+
+/// Wrong codes allowed before the password has to be given again.
+const MAX_FAILED_ATTEMPTS: u32 = 5;
+const RECOVERY_CODE_COUNT: usize = 10;
+
+/// A 6 digit, 30 second RFC 6238 TOTP for `secret`, a base32 string.
+fn totp(secret: &str, username: &str) -> Result<TOTP, ItoError> {
+    let secret = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|err| anyhow!("invalid TOTP secret: {err:?}"))?;
+    // One step of skew allows for clocks that are a little off.
+    Ok(TOTP::new(
+        Algorithm::SHA1,
+        6,
+        1,
+        30,
+        secret,
+        Some("ito".to_string()),
+        username.to_string(),
+    )?)
+}
+
+/// The time step `code` belongs to, if it is a current code for `totp`.
+fn current_step(totp: &TOTP, code: &str) -> Result<Option<u64>, ItoError> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let skew = u64::from(totp.skew);
+    let step = now / totp.step;
+    Ok((step - skew..=step + skew).find(|step| {
+        auth::constant_time_eq(totp.generate(step * totp.step).as_bytes(), code.as_bytes())
+    }))
+}
+
+/// Accepts `code` if it is a current code for `user_id`'s `secret` from a
+/// later time step than the last code they used, so a code seen over
+/// someone's shoulder can't be used again.
+pub fn accept_code(
+    conn: &Connection,
+    user_id: i64,
+    username: &str,
+    secret: &str,
+    code: &str,
+) -> Result<bool, ItoError> {
+    let Some(step) = current_step(&totp(secret, username)?, code.trim())? else {
+        return Ok(false);
+    };
+    let accepted = conn.execute(
+        "UPDATE users SET totp_last_step = ?1
+        WHERE id = ?2 AND (totp_last_step IS NULL OR totp_last_step < ?1)",
+        params![step, user_id],
+    )?;
+    Ok(accepted == 1)
+}
+
+/// Marks `code` used if it is one of `user_id`'s unused recovery codes. The
+/// codes are bcrypt hashed, so they are checked off the async runtime and
+/// without holding a database connection.
+async fn use_recovery_code(pool: &ItoPool, user_id: i64, code: String) -> Result<bool, ItoError> {
+    let unused = db::interact(pool, move |conn| -> Result<_, ItoError> {
+        let mut statement = conn.prepare(
+            "SELECT id, code_hash FROM totp_recovery_codes WHERE user_id = ? AND used_at IS NULL",
+        )?;
+        let unused = statement
+            .query_map([user_id], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(unused)
+    })
+    .await?;
+    let matching = tokio::task::spawn_blocking(move || -> Result<_, bcrypt::BcryptError> {
+        for (id, code_hash) in unused {
+            if bcrypt::verify(&code, &code_hash)? {
+                return Ok(Some(id));
+            }
+        }
+        Ok(None)
+    })
+    .await
+    .map_err(|err| anyhow!("failed to check recovery code: {err}"))??;
+    let Some(id) = matching else {
+        return Ok(false);
+    };
+    // Another sign in may have used the same code in the meantime.
+    let used = db::interact(pool, move |conn| {
+        conn.execute(
+            "UPDATE totp_recovery_codes
+            SET used_at = strftime('%Y-%m-%dT%H:%M:%SZ', 'now')
+            WHERE id = ? AND used_at IS NULL",
+            [id],
+        )
+        .map_err(ItoError::from)
+    })
+    .await?;
+    Ok(used == 1)
+}
+
+fn random_bytes<const N: usize>() -> Result<[u8; N], ItoError> {
+    let mut bytes = [0; N];
+    getrandom::fill(&mut bytes).map_err(|err| anyhow!("failed to generate secret: {err}"))?;
+    Ok(bytes)
+}
+
+/// Remembers that `user_id` has given their password, so they can finish
+/// signing in with a code.
+pub fn start_verification(session: &Session, user_id: i64) -> Result<(), ItoError> {
+    session.cycle_id();
+    session.insert(PENDING_USER_ID_KEY, user_id)?;
+    session.insert(FAILED_ATTEMPTS_KEY, 0)?;
+    Ok(())
+}
+
+#[derive(Template)]
+#[template(path = "totp_verify.html")]
+struct VerifyTemplate {
+    error: Option<String>,
+    csrf_token: String,
+}
+
+pub async fn verify_page(session: Session, csrf: CsrfToken) -> Result<Response, ItoError> {
+    if session.get::<i64>(PENDING_USER_ID_KEY)?.is_none() {
+        return Ok(Redirect::to("/login").into_response());
+    }
+    let template = VerifyTemplate {
+        error: None,
+        csrf_token: csrf.to_string(),
+    };
+    Ok((csrf, HtmlTemplate(template)).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct CodeInput {
+    code: String,
+}
+
+/// Finishes signing in with a code from the user's authenticator app, or
+/// one of their recovery codes, which then can't be used again.
+pub async fn verify(
+    State(pool): State<ItoPool>,
+    session: Session,
+    csrf: CsrfToken,
+    Form(input): Form<CodeInput>,
+) -> Result<Response, ItoError> {
+    let Some(user_id) = session.get::<i64>(PENDING_USER_ID_KEY)? else {
+        return Ok(Redirect::to("/login").into_response());
+    };
+    let code: String = input
+        .code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase();
+    let verified = if code.len() == 6 && code.bytes().all(|b| b.is_ascii_digit()) {
+        db::interact(&pool, move |conn| -> Result<_, ItoError> {
+            let (username, secret): (String, String) = conn.query_row(
+                "SELECT username, totp_secret FROM users WHERE id = ?",
+                [user_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )?;
+            accept_code(conn, user_id, &username, &secret, &code)
+        })
+        .await?
+    } else {
+        use_recovery_code(&pool, user_id, code).await?
+    };
+
+    if verified {
+        session.remove::<i64>(PENDING_USER_ID_KEY)?;
+        session.remove::<u32>(FAILED_ATTEMPTS_KEY)?;
+        users::sign_in(&session, user_id)?;
+        return Ok(Redirect::to("/").into_response());
+    }
+    let failed_attempts = session.get::<u32>(FAILED_ATTEMPTS_KEY)?.unwrap_or(0) + 1;
+    if failed_attempts >= MAX_FAILED_ATTEMPTS {
+        session.flush();
+        return Ok(Redirect::to("/login").into_response());
+    }
+    session.insert(FAILED_ATTEMPTS_KEY, failed_attempts)?;
+    let template = VerifyTemplate {
+        error: Some("Invalid code".to_string()),
+        csrf_token: csrf.to_string(),
+    };
+    Ok((StatusCode::UNAUTHORIZED, csrf, HtmlTemplate(template)).into_response())
+}
+
+/// Two-factor sign in is left to the user themselves, not an admin acting
+/// as them.
+fn refuse_impersonation(user: &User) -> Result<(), ItoError> {
+    match &user.impersonated_by {
+        Some(admin) => Err(ItoError {
+            err: anyhow!(
+                "{admin} can't change {}'s two-factor sign in",
+                user.username
+            ),
+            sc: StatusCode::FORBIDDEN,
+        }),
+        None => Ok(()),
+    }
+}
+
+#[derive(Template)]
+#[template(path = "totp_enroll.html")]
+struct EnrollTemplate {
+    error: Option<String>,
+    csrf_token: String,
+    qr_svg: String,
+    secret: String,
+}
+
+/// Shows a new secret to add to an authenticator app, as a QR code of its
+/// `otpauth://` URI and as text.
+pub async fn enroll_page(
+    user: User,
+    session: Session,
+    csrf: CsrfToken,
+) -> Result<impl IntoResponse, ItoError> {
+    refuse_impersonation(&user)?;
+    let secret = Secret::Raw(random_bytes::<20>()?.to_vec())
+        .to_encoded()
+        .to_string();
+    session.insert(ENROLLMENT_SECRET_KEY, &secret)?;
+    let template = EnrollTemplate {
+        error: None,
+        csrf_token: csrf.to_string(),
+        qr_svg: qr::svg(&totp(&secret, &user.username)?.get_url())?,
+        secret,
+    };
+    Ok((csrf, HtmlTemplate(template)))
+}
+
+#[derive(Template)]
+#[template(path = "totp_recovery_codes.html")]
+struct RecoveryCodesTemplate {
+    codes: Vec<String>,
+}
+
+/// Turns on two-factor sign in once the user confirms a code from the new
+/// secret, replacing any earlier secret and recovery codes.
+pub async fn enroll(
+    State(pool): State<ItoPool>,
+    user: User,
+    session: Session,
+    csrf: CsrfToken,
+    Form(input): Form<CodeInput>,
+) -> Result<Response, ItoError> {
+    refuse_impersonation(&user)?;
+    let Some(secret) = session.get::<String>(ENROLLMENT_SECRET_KEY)? else {
+        return Ok(Redirect::to("/account/totp").into_response());
+    };
+    let totp = totp(&secret, &user.username)?;
+    let Some(step) = current_step(&totp, input.code.trim())? else {
+        let template = EnrollTemplate {
+            error: Some("Invalid code, try again".to_string()),
+            csrf_token: csrf.to_string(),
+            qr_svg: qr::svg(&totp.get_url())?,
+            secret,
+        };
+        return Ok((StatusCode::BAD_REQUEST, csrf, HtmlTemplate(template)).into_response());
+    };
+    session.remove::<String>(ENROLLMENT_SECRET_KEY)?;
+
+    let codes = (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let hex: String = random_bytes::<5>()?
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect();
+            Ok(format!("{}-{}", &hex[..5], &hex[5..]))
+        })
+        .collect::<Result<Vec<_>, ItoError>>()?;
+    // Stored without the dash, the way codes are compared at sign in.
+    let stored_codes: Vec<_> = codes.iter().map(|code| code.replace('-', "")).collect();
+    let code_hashes = tokio::task::spawn_blocking(move || {
+        stored_codes
+            .into_iter()
+            .map(|code| bcrypt::hash(code, bcrypt::DEFAULT_COST))
+            .collect::<Result<Vec<_>, _>>()
+    })
+    .await
+    .map_err(|err| anyhow!("failed to hash recovery codes: {err}"))??;
+    db::interact(&pool, move |conn| -> Result<_, ItoError> {
+        let tx = conn.transaction()?;
+        // The confirmation code counts as used.
+        tx.execute(
+            "UPDATE users SET totp_secret = ?1, totp_last_step = ?2 WHERE id = ?3",
+            params![secret, step, user.id],
+        )?;
+        tx.execute(
+            "DELETE FROM totp_recovery_codes WHERE user_id = ?",
+            [user.id],
+        )?;
+        for code_hash in code_hashes {
+            tx.execute(
+                "INSERT INTO totp_recovery_codes (user_id, code_hash) VALUES (?1, ?2)",
+                params![user.id, code_hash],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    })
+    .await?;
+    Ok(HtmlTemplate(RecoveryCodesTemplate { codes }).into_response())
+}
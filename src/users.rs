@@ -0,0 +1,276 @@
+use std::{
+    io::{self, BufRead, Write},
+    sync::LazyLock,
+};
+
+use anyhow::{anyhow, bail, Result};
+use askama::Template;
+use axum::{
+    async_trait,
+    extract::{Form, FromRef, FromRequestParts, State},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::Deserialize;
+use tower_sessions::Session;
+
+use crate::{auth::Claims, csrf::CsrfToken, db, totp, HtmlTemplate, ItoError, ReadPool};
+
+const USER_ID_KEY: &str = "user_id";
+/// The user an admin signed in to the session is viewing ito as.
+const IMPERSONATING_USER_ID_KEY: &str = "impersonating_user_id";
+
+/// Hashed with the same cost as real passwords, so checking against it takes
+/// as long.
+static DUMMY_PASSWORD_HASH: LazyLock<String> = LazyLock::new(|| {
+    bcrypt::hash("not a password", bcrypt::DEFAULT_COST).expect("bcrypt can hash a constant")
+});
+
+/// The user signed in to the current session. Extracting it from a request
+/// without a valid session redirects to the login page.
+///
+/// While an admin is impersonating someone, this is the impersonated user,
+/// so every user-scoped query sees what they would.
+pub struct User {
+    pub id: i64,
+    pub username: String,
+    pub is_admin: bool,
+    /// The username of the admin impersonating this user, if any.
+    pub impersonated_by: Option<String>,
+}
+
+impl User {
+    /// Whether this user may modify a link owned by `owner_id`.
+    pub fn can_modify(&self, owner_id: Option<i64>) -> bool {
+        self.is_admin || owner_id == Some(self.id)
+    }
+
+    pub fn require_admin(&self) -> Result<(), ItoError> {
+        if self.is_admin {
+            Ok(())
+        } else {
+            Err(ItoError {
+                err: anyhow!("admin access required"),
+                sc: StatusCode::FORBIDDEN,
+            })
+        }
+    }
+}
+
+/// The id of the user signed in to `session`, if any.
+pub fn session_user_id(session: &Session) -> Option<i64> {
+    session.get(USER_ID_KEY).ok().flatten()
+}
+
+/// Makes the admin signed in to `session` act as `user_id` until
+/// `stop_impersonating`.
+pub fn start_impersonating(session: &Session, user_id: i64) -> Result<(), ItoError> {
+    session.insert(IMPERSONATING_USER_ID_KEY, user_id)?;
+    Ok(())
+}
+
+pub async fn stop_impersonating(session: Session) -> Result<Redirect, ItoError> {
+    session.remove::<i64>(IMPERSONATING_USER_ID_KEY)?;
+    Ok(Redirect::to("/admin"))
+}
+
+fn user_from_row(row: &Row) -> rusqlite::Result<User> {
+    Ok(User {
+        id: row.get(0)?,
+        username: row.get(1)?,
+        is_admin: row.get(2)?,
+        impersonated_by: None,
+    })
+}
+
+fn load_user(conn: &Connection, user_id: i64) -> rusqlite::Result<Option<User>> {
+    conn.query_row(
+        "SELECT id, username, is_admin FROM users WHERE id = ?",
+        [user_id],
+        user_from_row,
+    )
+    .optional()
+}
+
+/// The user a bearer token was issued to. Tokens name users by username.
+pub fn token_user(conn: &Connection, claims: &Claims) -> Result<User, ItoError> {
+    conn.query_row(
+        "SELECT id, username, is_admin FROM users WHERE username = ?",
+        [&claims.sub],
+        user_from_row,
+    )
+    .optional()?
+    .ok_or_else(|| ItoError {
+        err: anyhow!("the token's subject {:?} is not a user", claims.sub),
+        sc: StatusCode::FORBIDDEN,
+    })
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for User
+where
+    ReadPool: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let session = Session::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        let user_id = session
+            .get::<i64>(USER_ID_KEY)
+            .map_err(|err| ItoError::from(err).into_response())?;
+        let Some(user_id) = user_id else {
+            return Err(Redirect::to("/login").into_response());
+        };
+        let impersonating = session
+            .get::<i64>(IMPERSONATING_USER_ID_KEY)
+            .map_err(|err| ItoError::from(err).into_response())?;
+
+        let ReadPool(pool) = ReadPool::from_ref(state);
+        let user = db::interact(&pool, move |conn| {
+            let Some(user) = load_user(conn, user_id)? else {
+                return Ok(None);
+            };
+            // Only an admin's session can impersonate, and only users that
+            // still exist.
+            match impersonating.filter(|_| user.is_admin) {
+                Some(impersonated_id) => Ok(load_user(conn, impersonated_id)?
+                    .map(|impersonated| User {
+                        impersonated_by: Some(user.username.clone()),
+                        ..impersonated
+                    })
+                    .or(Some(user))),
+                None => Ok(Some(user)),
+            }
+        })
+        .await
+        .map_err(|err: ItoError| err.into_response())?;
+        match user {
+            Some(user) => Ok(user),
+            None => {
+                // The user was deleted since signing in.
+                session.flush();
+                Err(Redirect::to("/login").into_response())
+            }
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "login.html")]
+struct LoginTemplate {
+    error: Option<String>,
+    csrf_token: String,
+}
+
+pub async fn login_page(csrf: CsrfToken) -> impl IntoResponse {
+    let template = LoginTemplate {
+        error: None,
+        csrf_token: csrf.to_string(),
+    };
+    (csrf, HtmlTemplate(template))
+}
+
+#[derive(Deserialize)]
+pub struct LoginInput {
+    username: String,
+    password: String,
+}
+
+pub async fn login(
+    State(ReadPool(pool)): State<ReadPool>,
+    session: Session,
+    csrf: CsrfToken,
+    Form(input): Form<LoginInput>,
+) -> Result<Response, ItoError> {
+    let username = input.username.clone();
+    let user: Option<(i64, String, bool)> = db::interact(&pool, move |conn| {
+        conn.query_row(
+            "SELECT id, password_hash, totp_secret IS NOT NULL FROM users WHERE username = ?",
+            [username],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()
+        .map_err(ItoError::from)
+    })
+    .await?;
+    // Unknown usernames are checked against a dummy hash, so they take as
+    // long to reject as wrong passwords. bcrypt is slow on purpose, so it
+    // runs off the async runtime.
+    let (user, password_hash) = match user {
+        Some((id, password_hash, has_totp)) => (Some((id, has_totp)), Some(password_hash)),
+        None => (None, None),
+    };
+    let verified = tokio::task::spawn_blocking(move || {
+        let password_hash = password_hash.as_deref().unwrap_or(&DUMMY_PASSWORD_HASH);
+        bcrypt::verify(input.password, password_hash)
+    })
+    .await
+    .map_err(|err| anyhow!("failed to check password: {err}"))??;
+    let (user_id, has_totp) = match user {
+        Some(user) if verified => user,
+        _ => {
+            let template = LoginTemplate {
+                error: Some("Invalid username or password".to_string()),
+                csrf_token: csrf.to_string(),
+            };
+            return Ok((StatusCode::UNAUTHORIZED, csrf, HtmlTemplate(template)).into_response());
+        }
+    };
+    if has_totp {
+        totp::start_verification(&session, user_id)?;
+        return Ok(Redirect::to("/login/totp").into_response());
+    }
+    sign_in(&session, user_id)?;
+    Ok(Redirect::to("/").into_response())
+}
+
+/// Whether `password` matches `password_hash`. bcrypt is slow on purpose, so
+/// it runs off the async runtime.
+pub async fn verify_password(password: String, password_hash: String) -> Result<bool, ItoError> {
+    tokio::task::spawn_blocking(move || bcrypt::verify(password, &password_hash))
+        .await
+        .map_err(|err| anyhow!("failed to check password: {err}"))?
+        .map_err(ItoError::from)
+}
+
+/// Signs `user_id` in to `session`, once they have proven who they are.
+pub fn sign_in(session: &Session, user_id: i64) -> Result<(), ItoError> {
+    // New identity, new session id, so a session id planted before login is useless.
+    session.cycle_id();
+    session.insert(USER_ID_KEY, user_id)?;
+    Ok(())
+}
+
+pub async fn logout(session: Session) -> impl IntoResponse {
+    session.flush();
+    Redirect::to("/login")
+}
+
+/// Creates a user, reading the password from `ITO_PASSWORD` or, failing that, stdin.
+pub fn add_user(conn: &Connection, username: &str, is_admin: bool) -> Result<()> {
+    let password = match std::env::var("ITO_PASSWORD") {
+        Ok(password) => password,
+        Err(_) => {
+            eprint!("Password for {username}: ");
+            io::stderr().flush()?;
+            let mut password = String::new();
+            io::stdin().lock().read_line(&mut password)?;
+            password.trim_end_matches(['\r', '\n']).to_string()
+        }
+    };
+    if password.is_empty() {
+        bail!("password must not be empty");
+    }
+    let password_hash = bcrypt::hash(password, bcrypt::DEFAULT_COST)?;
+    conn.execute(
+        "INSERT INTO users (username, password_hash, is_admin, created_at)
+        VALUES (?1, ?2, ?3, strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))",
+        params![username, password_hash, is_admin],
+    )
+    .map_err(|err| anyhow!("failed to create user {username}: {err}"))?;
+    Ok(())
+}